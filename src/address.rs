@@ -1,44 +1,77 @@
 use crate::{
     error::*,
-    parser::{parse_imm, Rule},
+    expr::{fit_bounds, Expr},
+    parser::Rule,
 };
 use pest::iterators::Pair;
+use std::fmt;
 
+/// An address operand, which is an expression until it is resolved.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Address {
-    Label(String),
+    Expr(Expr),
     Short(u16),
 }
 
 impl TryFrom<Pair<'_, Rule>> for Address {
     type Error = Error;
     fn try_from(value: Pair<'_, Rule>) -> Result<Self> {
-        match value.as_rule() {
-            Rule::addr | Rule::label => Address::try_from(value.into_inner().next().unwrap()),
-            Rule::imm | Rule::hex_lit | Rule::dec_lit | Rule::oct_lit | Rule::bin_lit => {
-                Ok(Address::Short(parse_imm(value)?))
-            }
-            Rule::label_inner => Ok(Address::Label(value.as_str().to_string())),
-            other => Err(Error::Internal(format!(
-                "Cannot parse an Address from a Pair with Rule {:?}",
-                other
-            ))),
-        }
+        let span = value.as_span();
+        let expr = Expr::try_from(value).map_err(|e| e.at(span))?;
+        Address::from_expr(expr).map_err(|e| e.at(span))
     }
 }
 
 impl Address {
-    pub fn label_value(&self) -> Option<&str> {
+    pub const MAX: u16 = 0x0FFF;
+
+    /// Evaluates `expr` now if it is constant, or keeps it to be resolved once
+    /// labels are known.
+    pub fn from_expr(expr: Expr) -> Result<Self> {
+        if expr.is_constant() {
+            Ok(Address::Short(expr.eval_bounded(
+                Address::MAX,
+                &mut |_| {
+                    Err(Error::Internal(
+                        "Constant expression referenced a symbol".into(),
+                    ))
+                },
+            )?))
+        } else {
+            Ok(Address::Expr(expr))
+        }
+    }
+
+    pub fn unresolved(&self) -> Option<&Expr> {
         match self {
-            Address::Label(s) => Some(s.as_str()),
+            Address::Expr(e) => Some(e),
             _ => None,
         }
     }
 
     pub fn to_resolved(&self) -> Result<u16> {
         match self {
-            Address::Label(s) => Err(Error::UnresolvedLabel(s.clone())),
+            Address::Expr(e) => Err(Error::UnresolvedLabel(e.to_string())),
             Address::Short(n) => Ok(*n),
         }
     }
+
+    pub fn resolve(&mut self, val: i64) -> Result<()> {
+        if self.unresolved().is_none() {
+            return Err(Error::Internal(
+                "Tried to resolve an already resolved Address".to_string(),
+            ));
+        }
+        *self = Address::Short(fit_bounds(val, Address::MAX)?);
+        Ok(())
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Expr(e) => write!(f, "{e}"),
+            Address::Short(n) => write!(f, "{n:#X}"),
+        }
+    }
 }
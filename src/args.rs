@@ -1,17 +1,160 @@
-use clap::Parser;
+use crate::{
+    assembler::Options, output::OutputFormat, parser::Syntax, quirks::Platform,
+    symbols::SymbolFormat,
+};
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
-#[clap(about = env!("CARGO_PKG_DESCRIPTION"), version = env!("CARGO_PKG_VERSION"))]
+#[clap(
+    about = env!("CARGO_PKG_DESCRIPTION"),
+    version = env!("CARGO_PKG_VERSION"),
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Args {
-    #[clap(help = "File to compile", empty_values = false)]
-    pub input: PathBuf,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+    #[clap(help = "File to compile", empty_values = false, required = true)]
+    pub input: Option<PathBuf>,
     #[clap(
-        help = "Output path. Defaults to [input path].bin",
+        help = "Output path. Defaults to the input path with the format's extension",
         short = 'o',
         long = "--output"
     )]
     pub output: Option<PathBuf>,
+    #[clap(
+        help = "Output format: bin, ihex (.hex), hexdump (.txt), c (.h) or rust (.rs)",
+        long = "--format",
+        default_value = "bin",
+        value_parser = parse_output_format
+    )]
+    pub format: OutputFormat,
+    #[clap(flatten)]
+    pub build: BuildArgs,
+    #[clap(
+        help = "Also write a listing of each line's address and bytes, and the symbol table, to this path",
+        long = "--listing"
+    )]
+    pub listing: Option<PathBuf>,
+    #[clap(
+        help = "Also write the labels next to the output, as plain (.map), json (.symbols.json) or octo (.sym). Can be repeated",
+        long = "--symbols",
+        value_name = "FORMAT",
+        value_parser = parse_symbol_format
+    )]
+    pub symbols: Vec<SymbolFormat>,
+    #[clap(
+        help = "Also write JSON debug info mapping addresses to source lines next to the output, as .debug.json",
+        long = "--debug-info"
+    )]
+    pub debug_info: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[clap(about = "Disassembles a binary to source that chip8c can assemble again")]
+    Disasm {
+        #[clap(help = "Binary to disassemble", empty_values = false)]
+        input: PathBuf,
+        #[clap(
+            help = "Output path. Defaults to standard output",
+            short = 'o',
+            long = "--output"
+        )]
+        output: Option<PathBuf>,
+        #[clap(
+            help = "Address the binary is loaded at",
+            long = "--origin",
+            default_value = "0x200",
+            value_parser = parse_origin
+        )]
+        origin: u16,
+        #[clap(
+            help = "Platform whose instructions to decode: vip, chip48, schip, xochip or modern",
+            long = "--target",
+            default_value = "modern",
+            value_parser = parse_target
+        )]
+        target: Platform,
+    },
+    #[clap(about = "Assembles a file and runs it in a headless emulator")]
+    Run {
+        #[clap(help = "File to run", empty_values = false)]
+        input: PathBuf,
+        #[clap(
+            help = "Number of instructions to execute",
+            long = "--cycles",
+            default_value = "1000"
+        )]
+        cycles: u64,
+        #[clap(help = "Print the screen when the run ends", long = "--dump-screen")]
+        dump_screen: bool,
+        #[clap(
+            help = "Hold keys from a cycle onward, e.g. 100:5A holds 5 and A and 200: releases them",
+            long = "--keys",
+            value_parser = parse_keys
+        )]
+        keys: Vec<(u64, u16)>,
+        #[clap(flatten)]
+        build: BuildArgs,
+    },
+}
+
+/// How to assemble the input, for both assembling it to a file and running
+/// it.
+#[derive(Debug, clap::Args)]
+pub struct BuildArgs {
+    #[clap(
+        help = "Address the program is loaded at. Defaults to the address a leading .org gives, or 0x200. A leading .org that disagrees is an error",
+        long = "--origin",
+        value_parser = parse_origin
+    )]
+    pub origin: Option<u16>,
+    #[clap(
+        help = "Size of the target's memory, e.g. 4096, 0x1000 or 4K. Defaults to 64K for xochip and 4K otherwise",
+        long = "--memory-size",
+        value_parser = parse_memory_size
+    )]
+    pub memory_size: Option<u32>,
+    #[clap(
+        help = "Platform to check against, and to emulate with run: vip, chip48, schip, xochip or modern",
+        long = "--target",
+        default_value = "modern",
+        value_parser = parse_target
+    )]
+    pub target: Platform,
+    #[clap(
+        help = "Source syntax: chip8c or octo. Defaults to octo for .8o files and chip8c otherwise",
+        long = "--syntax",
+        value_parser = parse_syntax
+    )]
+    pub syntax: Option<Syntax>,
+    #[clap(
+        help = "Directory to search for included files, after the including file's own. Can be repeated",
+        short = 'I',
+        long = "--include"
+    )]
+    pub include: Vec<PathBuf>,
+    #[clap(
+        help = "Define a constant, e.g. DEBUG or LEVEL=3. Can be repeated",
+        short = 'D',
+        long = "--define",
+        value_name = "NAME[=VALUE]",
+        value_parser = parse_define
+    )]
+    pub defines: Vec<(String, i64)>,
+}
+
+impl BuildArgs {
+    pub fn options(&self) -> Options {
+        Options {
+            origin: self.origin,
+            memory_size: self.memory_size.unwrap_or(self.target.memory_size()),
+            target: self.target,
+            defines: self.defines.clone(),
+        }
+    }
 }
 
 impl Args {
@@ -19,3 +162,69 @@ impl Args {
         <Args as Parser>::parse()
     }
 }
+
+fn parse_number(s: &str) -> Result<u32, String> {
+    let (digits, radix) = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => (hex, 16),
+        None => (s, 10),
+    };
+    u32::from_str_radix(digits, radix).map_err(|e| format!("'{s}' is not a number: {e}"))
+}
+
+fn parse_origin(s: &str) -> Result<u16, String> {
+    let val = parse_number(s)?;
+    u16::try_from(val).map_err(|_| format!("{val:#X} is past the end of addressable memory"))
+}
+
+fn parse_memory_size(s: &str) -> Result<u32, String> {
+    let val = match s.strip_suffix('K').or_else(|| s.strip_suffix('k')) {
+        Some(kb) => parse_number(kb)?.saturating_mul(1024),
+        None => parse_number(s)?,
+    };
+    if val > 0x10000 {
+        Err(format!("{val:#X} bytes is more than CHIP-8 can address"))
+    } else {
+        Ok(val)
+    }
+}
+
+fn parse_keys(s: &str) -> Result<(u64, u16), String> {
+    let (cycle, keys) = s
+        .split_once(':')
+        .ok_or_else(|| format!("'{s}' is not of the form CYCLE:KEYS"))?;
+    let cycle = cycle
+        .parse()
+        .map_err(|e| format!("'{cycle}' is not a cycle: {e}"))?;
+    let keys = keys.chars().try_fold(0, |held, c| match c.to_digit(16) {
+        Some(key) => Ok(held | 1 << key),
+        None => Err(format!("'{c}' is not a key")),
+    })?;
+    Ok((cycle, keys))
+}
+
+/// Parses `NAME=value`, or `NAME` alone for a value of 1.
+fn parse_define(s: &str) -> Result<(String, i64), String> {
+    let (name, val) = s.split_once('=').unwrap_or((s, "1"));
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(format!("'{name}' is not a valid constant name"));
+    }
+    Ok((name.to_string(), parse_number(val)?.into()))
+}
+
+fn parse_target(s: &str) -> Result<Platform, String> {
+    s.parse()
+}
+
+fn parse_syntax(s: &str) -> Result<Syntax, String> {
+    s.parse()
+}
+
+fn parse_output_format(s: &str) -> Result<OutputFormat, String> {
+    s.parse()
+}
+
+fn parse_symbol_format(s: &str) -> Result<SymbolFormat, String> {
+    s.parse()
+}
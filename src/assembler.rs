@@ -1,63 +1,845 @@
-use crate::{error::*, instruction::Instruction, parser::Rule};
-use pest::iterators::Pair;
-use std::{collections::HashMap, io::prelude::*};
+use crate::{
+    error::*,
+    expr::Expr,
+    immediate::Immediate,
+    instruction::{Extension, Instruction},
+    parser::Rule,
+    quirks::Platform,
+    register::Register,
+    source::{self, Origin, Source},
+    symbols::json_string,
+};
+use pest::{iterators::Pair, Span};
+use std::{
+    collections::{HashMap, HashSet},
+    io::prelude::*,
+};
 
+/// A statement after parsing, ready to be placed in memory. Front-ends other
+/// than the native grammar produce these directly.
 #[derive(Debug)]
-enum Item<'a> {
-    Inst(Instruction),
-    Label(&'a str),
+pub(crate) enum Item<'a> {
+    /// An instruction, with where it and its operands are written.
+    Inst(Instruction, InstSpans<'a>),
+    Bytes(Vec<(Immediate, Span<'a>)>),
+    Words(Vec<(Immediate, Span<'a>)>),
+    Sprite(Vec<u8>),
+    /// The contents of a file included with `incbin`.
+    Binary(Vec<u8>),
+    Fill {
+        count: u16,
+        value: u8,
+    },
+    Org(u16),
+    /// A label and the span that defines it. Front-ends may synthesize names
+    /// that do not appear in the source; see `is_synthesized`.
+    Label(String, Span<'a>),
+    Constant(Span<'a>, Expr),
+}
+
+/// Where an instruction and its operands are written, so that errors and
+/// warnings can point at the part they are about.
+#[derive(Debug, Clone)]
+pub(crate) struct InstSpans<'a> {
+    inst: Span<'a>,
+    /// The registers and expressions, in the order they are written.
+    operands: Vec<Span<'a>>,
+    /// The index in `operands` of the expression, if there is one.
+    expr: Option<usize>,
+}
+
+impl<'a> InstSpans<'a> {
+    /// Spans for an instruction whose operands cannot be told apart.
+    pub fn whole(inst: Span<'a>) -> Self {
+        InstSpans {
+            inst,
+            operands: Vec::new(),
+            expr: None,
+        }
+    }
+
+    fn parse(p: &Pair<'a, Rule>) -> Self {
+        let operands: Vec<_> = p
+            .clone()
+            .into_inner()
+            .flatten()
+            .filter(|p| matches!(p.as_rule(), Rule::register | Rule::addr | Rule::operand))
+            .collect();
+        InstSpans {
+            inst: Assembler::statement_span(p),
+            expr: operands.iter().position(|p| p.as_rule() != Rule::register),
+            operands: operands.iter().map(Pair::as_span).collect(),
+        }
+    }
+
+    /// The span of operand `index`, or of the whole instruction if it has no
+    /// such operand.
+    fn operand(&self, index: usize) -> Span<'a> {
+        *self.operands.get(index).unwrap_or(&self.inst)
+    }
+
+    /// The span of the expression, or of the whole instruction if it has
+    /// none.
+    fn expr(&self) -> Span<'a> {
+        self.expr
+            .map_or_else(|| self.inst, |index| self.operand(index))
+    }
+}
+
+impl Item<'_> {
+    fn size(&self) -> u16 {
+        match self {
+            Item::Inst(inst, _) => inst.size(),
+            Item::Bytes(values) => values.len() as u16,
+            Item::Words(values) => values.len() as u16 * 2,
+            Item::Sprite(rows) => rows.len() as u16,
+            Item::Binary(bytes) => bytes.len() as u16,
+            Item::Fill { count, .. } => *count,
+            Item::Org(_) | Item::Label(..) | Item::Constant(..) => 0,
+        }
+    }
+
+    /// The item's encoding, once its arguments are resolved.
+    fn bytes(&self) -> Result<Vec<u8>> {
+        Ok(match self {
+            Item::Inst(inst, _) => inst.as_bytes()?,
+            Item::Bytes(values) => values
+                .iter()
+                .map(|(val, _)| Ok(val.to_resolved()? as u8))
+                .collect::<Result<_>>()?,
+            Item::Words(values) => values
+                .iter()
+                .map(|(val, _)| Ok(val.to_resolved()?.to_be_bytes()))
+                .collect::<Result<Vec<_>>>()?
+                .concat(),
+            Item::Sprite(rows) | Item::Binary(rows) => rows.clone(),
+            Item::Fill { count, value } => vec![*value; *count as usize],
+            Item::Org(_) | Item::Label(..) | Item::Constant(..) => Vec::new(),
+        })
+    }
+}
+
+/// How a program is assembled. By default it is loaded at 0x200 in 4K of
+/// memory.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Address the program is loaded at. If it is not given, a leading
+    /// `.org` gives it, or it is 0x200; if it is, a leading `.org` must
+    /// agree.
+    pub origin: Option<u16>,
+    /// Size of the target's memory in bytes.
+    pub memory_size: u32,
+    /// Platform the program is checked against.
+    pub target: Platform,
+    /// Constants defined before the program, such as with `-D`.
+    pub defines: Vec<(String, i64)>,
+}
+
+impl Options {
+    /// Where programs are loaded unless they say otherwise.
+    pub const DEFAULT_ORIGIN: u16 = 0x200;
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            origin: None,
+            memory_size: 0x1000,
+            target: Platform::default(),
+            defines: Vec::new(),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Assembler<'a> {
-    instructions: Vec<Instruction>,
-    labels: HashMap<&'a str, u16>,
+    memory_size: u32,
+    /// The load address the options give, if any.
+    origin_option: Option<u16>,
+    end: u32,
+    overflowed: bool,
+    items: Vec<Item<'a>>,
+    /// Where each item came from.
+    origins: Vec<&'a Origin>,
+    /// The address of each item and the statement that placed it.
+    placements: Vec<(u32, Span<'a>)>,
+    /// Where the statement being built came from.
+    origin: &'a Origin,
+    labels: HashMap<String, u16>,
+    /// Labels renamed in macro expansions, which are not exported.
+    renamed: HashSet<String>,
+    constants: HashMap<String, Expr>,
+    definitions: HashMap<String, (Span<'a>, &'a Origin)>,
+    sprites: HashMap<u16, u8>,
+    /// Global and anonymous labels in the order they are defined, each with
+    /// the number of items placed before it.
+    globals: Vec<(usize, String)>,
+    anonymous: Vec<(usize, String)>,
+    /// The `.if` blocks the next statement is in, innermost last.
+    conditions: Vec<Condition<'a>>,
+    warnings: Vec<Warning>,
 }
 
-impl<'a> Assembler<'a> {
-    const PROGRAM_START: u16 = 0x200;
+/// The labels that local and anonymous references in one item resolve to.
+struct Scope<'s> {
+    globals: &'s [(usize, String)],
+    anonymous: &'s [(usize, String)],
+    /// The index of the item, which is the number of items placed before it.
+    index: usize,
+}
 
-    pub fn build(pairs: impl Iterator<Item = Pair<'a, Rule>>) -> Result<Assembler<'a>> {
-        let mut asm = Assembler {
-            instructions: Default::default(),
-            labels: Default::default(),
+impl Scope<'_> {
+    /// The full name of the label a reference refers to. `.name` is local to
+    /// the last global label before the item, and `+` or `-` refers to the
+    /// next anonymous label after it or the last one before it, with each
+    /// extra `+` or `-` skipping one more.
+    fn qualify(&self, name: &str) -> String {
+        let before = |labels: &[(usize, String)]| {
+            labels.partition_point(|(placed, _)| *placed <= self.index)
+        };
+        let anonymous = |index: Option<usize>| {
+            index
+                .and_then(|index| self.anonymous.get(index))
+                .map_or_else(|| name.to_string(), |(_, label)| label.clone())
         };
-        for p in pairs {
+        if name.starts_with('.') {
+            match before(self.globals).checked_sub(1) {
+                Some(scope) => format!("{}{name}", self.globals[scope].1),
+                None => name.to_string(),
+            }
+        } else if name.bytes().all(|c| c == b'+') {
+            anonymous(Some(before(self.anonymous) + name.len() - 1))
+        } else if name.bytes().all(|c| c == b'-') {
+            anonymous(before(self.anonymous).checked_sub(name.len()))
+        } else {
+            name.to_string()
+        }
+    }
+}
+
+/// An `.if` block that is still open.
+#[derive(Debug)]
+struct Condition<'a> {
+    span: Span<'a>,
+    /// Whether the statements in the current branch are assembled.
+    active: bool,
+    /// Whether a branch has been taken, so later ones are not. Set from the
+    /// start if the whole block is excluded.
+    taken: bool,
+    /// Whether the block has reached its `.else`.
+    otherwise: bool,
+}
+
+impl<'a> Assembler<'a> {
+    const MAX_SPRITE_HEIGHT: u16 = 0xF;
+    const BYTE_MAX: u16 = 0xFF;
+    const WORD_MAX: u16 = 0xFFFF;
+
+    /// Assembles statements parsed from a single text, without macros.
+    #[cfg(test)]
+    pub fn build(
+        pairs: impl Iterator<Item = Pair<'a, Rule>>,
+        options: &Options,
+    ) -> Result<Assembler<'a>> {
+        Assembler::build_statements(pairs.map(|p| (p, &source::TOP_LEVEL)), options)
+    }
+
+    /// Assembles a program once its macros are expanded.
+    pub fn build_source(source: &'a Source, options: &Options) -> Result<Assembler<'a>> {
+        Assembler::build_statements(source.statements()?, options)
+    }
+
+    fn build_statements(
+        statements: impl IntoIterator<Item = (Pair<'a, Rule>, &'a Origin)>,
+        options: &Options,
+    ) -> Result<Assembler<'a>> {
+        let mut asm = Assembler::new(options);
+        asm.constants.extend(
+            options
+                .defines
+                .iter()
+                .map(|(name, val)| (name.clone(), Expr::Num(*val))),
+        );
+        let mut errors = Vec::new();
+        for (p, origin) in statements {
             if p.as_rule() == Rule::EOF {
                 break;
             }
-            match asm.parse_item(p)? {
-                Item::Inst(inst) => asm.instructions.push(inst),
-                Item::Label(lbl) => {
-                    if asm
-                        .labels
-                        .insert(
-                            lbl,
-                            (asm.instructions.len() * 2) as u16 + Assembler::PROGRAM_START,
-                        )
-                        .is_some()
-                    {
-                        return Err(Error::DuplicateLabel(lbl.to_string()));
+            asm.origin = origin;
+            if let Err(e) = asm.build_statement(p) {
+                errors.push(origin.locate(e));
+            }
+        }
+        for condition in asm.conditions.drain(..) {
+            errors.push(Error::Unclosed(".if".into()).at(condition.span));
+        }
+        asm.finish(errors, options)
+    }
+
+    /// Assembles statements that another front-end has already parsed, each
+    /// with the span errors about it should point at.
+    pub(crate) fn build_items(
+        items: impl IntoIterator<Item = (Item<'a>, Span<'a>)>,
+        options: &Options,
+    ) -> Result<Assembler<'a>> {
+        let mut asm = Assembler::new(options);
+        let mut errors = Vec::new();
+        for (item, span) in items {
+            if let Err(e) = asm.add(item, span) {
+                errors.push(e);
+            }
+        }
+        asm.finish(errors, options)
+    }
+
+    fn new(options: &Options) -> Assembler<'a> {
+        Assembler {
+            memory_size: options.memory_size,
+            origin_option: options.origin,
+            end: options.origin.unwrap_or(Options::DEFAULT_ORIGIN).into(),
+            overflowed: false,
+            items: Default::default(),
+            origins: Default::default(),
+            placements: Default::default(),
+            origin: &source::TOP_LEVEL,
+            labels: Default::default(),
+            renamed: Default::default(),
+            constants: Default::default(),
+            definitions: Default::default(),
+            sprites: Default::default(),
+            globals: Default::default(),
+            anonymous: Default::default(),
+            conditions: Default::default(),
+            warnings: Default::default(),
+        }
+    }
+
+    /// Resolves arguments once every statement is placed, and collects the
+    /// errors from both passes.
+    fn finish(mut self, mut errors: Vec<Error>, options: &Options) -> Result<Assembler<'a>> {
+        errors.extend(self.resolve_args());
+        errors.extend(self.check_target(options.target));
+        self.lint(options.target);
+        errors.sort_by_key(Error::line_col);
+        match errors.len() {
+            0 => Ok(self),
+            1 => Err(errors.pop().unwrap()),
+            _ => Err(Error::Multiple(errors)),
+        }
+    }
+
+    /// The address the program is loaded at, which an `.org` before anything
+    /// is placed may have changed from the origin.
+    pub fn start(&self) -> u16 {
+        self.placements.first().map_or(self.end, |(addr, _)| *addr) as u16
+    }
+
+    pub fn write_bin(&self, mut dest: impl Write) -> Result<()> {
+        for item in &self.items {
+            dest.write_all(&item.bytes()?)?;
+        }
+        Ok(())
+    }
+
+    /// Writes each line that places an item or defines a label, next to its
+    /// address and the bytes it assembled to, followed by every label sorted
+    /// by address.
+    pub fn write_listing(&self, mut dest: impl Write) -> Result<()> {
+        const BYTES_PER_ROW: usize = 4;
+        let width = BYTES_PER_ROW * 3 - 1;
+        let labels = self.symbols();
+        // Labels are listed before the first item at or after them, unless
+        // they are on the same line as it.
+        let mut defined = labels
+            .iter()
+            .filter_map(|(addr, name)| {
+                let (span, origin) = self.definitions.get(*name)?;
+                Some((u32::from(*addr), span, *origin))
+            })
+            .collect::<Vec<_>>();
+        defined.sort_by_key(|(addr, span, _)| (*addr, span.start()));
+        let mut defined = defined.into_iter().peekable();
+        let placed = self.items.iter().zip(&self.origins).zip(&self.placements);
+        for ((item, origin), (addr, span)) in placed {
+            let line = Assembler::source_line(span, origin);
+            while let Some((label, label_span, label_origin)) =
+                defined.next_if(|(label, _, _)| label <= addr)
+            {
+                let label_line = Assembler::source_line(label_span, label_origin);
+                if label_line != line {
+                    writeln!(dest, "{label:04X}  {:width$}  {label_line}", "")?;
+                }
+            }
+            let bytes = item.bytes()?;
+            if bytes.is_empty() {
+                continue;
+            }
+            for (row, chunk) in bytes.chunks(BYTES_PER_ROW).enumerate() {
+                let hex = chunk
+                    .iter()
+                    .map(|byte| format!("{byte:02X}"))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let addr = addr + (row * BYTES_PER_ROW) as u32;
+                match row {
+                    0 => writeln!(dest, "{addr:04X}  {hex:width$}  {line}")?,
+                    _ => writeln!(dest, "{addr:04X}  {hex}")?,
+                }
+            }
+        }
+        for (label, span, origin) in defined {
+            let line = Assembler::source_line(span, origin);
+            writeln!(dest, "{label:04X}  {:width$}  {line}", "")?;
+        }
+        writeln!(dest, "\nSymbols:")?;
+        for (addr, name) in labels {
+            writeln!(dest, "{addr:04X}  {name}")?;
+        }
+        Ok(())
+    }
+
+    /// Writes JSON that maps each placed item's addresses to the line and
+    /// column that placed it, and the macro calls it was expanded from. It also
+    /// lists each global label's scope, from its address to the next global
+    /// label's, with the local labels in it, and the ranges of memory that hold
+    /// data rather than instructions. `program` names the file statements that
+    /// are not in an included file or macro are in.
+    pub fn write_debug_info(&self, program: &str, mut dest: impl Write) -> Result<()> {
+        let file = |path: Option<&str>| json_string(path.unwrap_or(program));
+        let mut lines = Vec::new();
+        let mut data: Vec<(u32, u32)> = Vec::new();
+        let placed = self.items.iter().zip(&self.origins).zip(&self.placements);
+        for ((item, origin), (addr, span)) in placed {
+            let size = u32::from(item.size());
+            if size == 0 {
+                continue;
+            }
+            let (line, col) = span.start_pos().line_col();
+            let calls = origin
+                .calls()
+                .iter()
+                .map(|call| {
+                    format!(
+                        "{{\"macro\": {}, \"file\": {}, \"line\": {}, \"column\": {}}}",
+                        json_string(&call.name),
+                        file(call.path.as_deref()),
+                        call.line,
+                        call.col
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            lines.push(format!(
+                "{{\"address\": {addr}, \"size\": {size}, \"file\": {}, \"line\": {line}, \
+                 \"column\": {col}, \"expanded_from\": [{calls}]}}",
+                file(origin.path())
+            ));
+            if !matches!(item, Item::Inst(..)) {
+                match data.last_mut() {
+                    Some((_, end)) if *end == *addr => *end += size,
+                    _ => data.push((*addr, addr + size)),
+                }
+            }
+        }
+        let scopes = self
+            .globals
+            .iter()
+            .enumerate()
+            .map(|(i, (_, name))| {
+                let start = u32::from(self.labels[name]);
+                let end = self
+                    .globals
+                    .get(i + 1)
+                    .map_or(self.end, |(_, next)| u32::from(self.labels[next]));
+                let prefix = format!("{name}.");
+                let locals = self
+                    .symbols()
+                    .into_iter()
+                    .filter(|(_, label)| label.starts_with(&prefix))
+                    .map(|(addr, label)| {
+                        format!("{{\"name\": {}, \"address\": {addr}}}", json_string(label))
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!(
+                    "{{\"name\": {}, \"start\": {start}, \"end\": {end}, \"labels\": [{locals}]}}",
+                    json_string(name)
+                )
+            })
+            .collect();
+        let data = data
+            .into_iter()
+            .map(|(start, end)| format!("{{\"start\": {start}, \"end\": {end}}}"))
+            .collect();
+        writeln!(dest, "{{")?;
+        for (key, entries, last) in [
+            ("lines", lines, false),
+            ("scopes", scopes, false),
+            ("data", data, true),
+        ] {
+            let comma = if last { "" } else { "," };
+            match entries.is_empty() {
+                true => writeln!(dest, "  \"{key}\": []{comma}")?,
+                false => writeln!(
+                    dest,
+                    "  \"{key}\": [\n    {}\n  ]{comma}",
+                    entries.join(",\n    ")
+                )?,
+            }
+        }
+        writeln!(dest, "}}")?;
+        Ok(())
+    }
+
+    /// Every label written in the program with its address, sorted by
+    /// address and then by name.
+    pub fn symbols(&self) -> Vec<(u16, &str)> {
+        let mut symbols: Vec<(u16, &str)> = self
+            .labels
+            .iter()
+            .filter(|(name, _)| !is_synthesized(name) && !self.renamed.contains(*name))
+            .map(|(name, addr)| (*addr, name.as_str()))
+            .collect();
+        symbols.sort_unstable();
+        symbols
+    }
+
+    /// The location and text of the line a span starts on, for listings.
+    fn source_line(span: &Span<'_>, origin: &Origin) -> String {
+        let start = span.start_pos();
+        let (line, _) = start.line_col();
+        let text = start.line_of().trim_end();
+        match origin.path() {
+            Some(path) => format!("{path}:{line:<4} {text}"),
+            None => format!("{line:<4} {text}"),
+        }
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    fn build_statement(&mut self, p: Pair<'a, Rule>) -> Result<()> {
+        let span = Assembler::statement_span(&p);
+        let statement = Assembler::statement(&p);
+        match statement.as_rule() {
+            Rule::cond_if
+            | Rule::cond_ifdef
+            | Rule::cond_ifndef
+            | Rule::cond_else
+            | Rule::cond_endif => {
+                return self.condition(statement).map_err(|e| e.at(span));
+            }
+            _ if self.conditions.iter().any(|c| !c.active) => return Ok(()),
+            _ => (),
+        }
+        let form = p
+            .clone()
+            .into_inner()
+            .next()
+            .filter(|p| p.as_rule() == Rule::instruction)
+            .and_then(|p| p.into_inner().next())
+            .map(|p| p.as_rule());
+        let item = match self.parse_item(p) {
+            Ok(item) => item,
+            Err(e) => {
+                // Keep later labels where they would be had the instruction
+                // been valid, so it doesn't cause errors elsewhere.
+                if let Some(form) = form {
+                    self.end += u32::from(Instruction::form_size(form));
+                }
+                return Err(e.at(span));
+            }
+        };
+        self.add(item, span)
+    }
+
+    /// Places an item, or records a label, constant or origin change.
+    fn add(&mut self, item: Item<'a>, span: Span<'a>) -> Result<()> {
+        let addr = self.end;
+        match item {
+            Item::Label(name, span) => {
+                let addr = match u16::try_from(addr) {
+                    Ok(addr) => addr,
+                    Err(_) if self.overflowed => addr as u16,
+                    Err(_) => return Err(self.overflow(&span, addr)),
+                };
+                let renamed = self.origin.is_renamed(&name);
+                let name = self.full_name(name);
+                self.define(&name, span, Error::DuplicateLabel(name.clone()))?;
+                if renamed {
+                    self.renamed.insert(name.clone());
+                }
+                self.labels.insert(name, addr);
+            }
+            Item::Constant(name, val) => {
+                // A constant that is defined but has no definition in the
+                // source was defined before it, such as with `-D`.
+                if self.constants.contains_key(name.as_str())
+                    && !self.definitions.contains_key(name.as_str())
+                {
+                    return Err(Error::DuplicateConstant(name.as_str().into()).at(name));
+                }
+                self.define(
+                    name.as_str(),
+                    name,
+                    Error::DuplicateConstant(name.as_str().into()),
+                )?;
+                self.constants.insert(name.as_str().into(), val);
+            }
+            // An .org before anything is placed sets the load address
+            // rather than padding up to it.
+            Item::Org(target) if self.items.is_empty() && self.labels.is_empty() => {
+                match self.origin_option {
+                    Some(origin) if origin != target => {
+                        return Err(Error::OrgConflict {
+                            origin,
+                            org: target,
+                        }
+                        .at(span))
                     }
+                    _ => self.end = target.into(),
+                }
+            }
+            Item::Org(target) if u32::from(target) < addr => {
+                return Err(Error::OrgBackwards {
+                    from: addr,
+                    to: target,
                 }
+                .at(span));
+            }
+            Item::Org(target) => {
+                let count = (u32::from(target) - addr) as u16;
+                self.place(Item::Fill { count, value: 0 }, &span)?;
+            }
+            item => {
+                if let (Item::Sprite(rows), Ok(addr)) = (&item, u16::try_from(addr)) {
+                    self.sprites.insert(addr, rows.len() as u8);
+                }
+                self.place(item, &span)?;
             }
         }
-        asm.resolve_args()?;
-        Ok(asm)
+        Ok(())
     }
 
-    pub fn write_bin(&self, mut dest: impl Write) -> Result<()> {
-        for inst in &self.instructions {
-            dest.write_all(&inst.as_bytes()?)?;
+    /// Gives a label being defined its full name: a local label is prefixed
+    /// with the global label before it, and anonymous labels are numbered.
+    fn full_name(&mut self, name: String) -> String {
+        let placed = self.items.len();
+        if name.starts_with('.') {
+            match self.globals.last() {
+                Some((_, scope)) => format!("{scope}{name}"),
+                None => name,
+            }
+        } else if name == "@@" {
+            let name = format!("@@{}", self.anonymous.len() + 1);
+            self.anonymous.push((placed, name.clone()));
+            name
+        } else {
+            // Labels in a macro's body are renamed in each expansion, so they
+            // are not scopes the caller's local labels could belong to.
+            if self.origin.calls().is_empty() && !is_synthesized(&name) {
+                self.globals.push((placed, name.clone()));
+            }
+            name
+        }
+    }
+
+    /// Appends an item at the location counter, checking that it fits in memory.
+    fn place(&mut self, item: Item<'a>, span: &Span<'a>) -> Result<()> {
+        let addr = self.end;
+        self.end += u32::from(item.size());
+        self.items.push(item);
+        self.origins.push(self.origin);
+        self.placements.push((addr, *span));
+        // Only the first item past the end is reported, since everything after
+        // it is past the end too.
+        if self.end > self.memory_size && !self.overflowed {
+            self.overflowed = true;
+            return Err(self.overflow(span, addr));
         }
         Ok(())
     }
 
+    fn overflow(&self, span: &Span<'a>, addr: u32) -> Error {
+        Error::Overflow {
+            item: span.as_str().to_string(),
+            addr,
+            memory_size: self.memory_size,
+        }
+        .at(*span)
+    }
+
+    /// Records where a label or constant is defined, failing with `duplicate`
+    /// if the name is already taken.
+    fn define(&mut self, name: &str, span: Span<'a>, duplicate: Error) -> Result<()> {
+        if let Some((first, origin)) = self.definitions.get(name) {
+            let note = origin.note(*first, "first defined here");
+            return Err(duplicate.at(span).with_notes(&[note]));
+        }
+        self.definitions
+            .insert(name.to_string(), (span, self.origin));
+        Ok(())
+    }
+
+    /// Opens, switches or closes an `.if` block.
+    fn condition(&mut self, p: Pair<'a, Rule>) -> Result<()> {
+        let enclosing = self.conditions.iter().all(|c| c.active);
+        let rule = p.as_rule();
+        let span = p.as_span();
+        let active = match rule {
+            Rule::cond_else | Rule::cond_endif => {
+                let keyword = if rule == Rule::cond_else {
+                    ".else"
+                } else {
+                    ".endif"
+                };
+                let condition = match self.conditions.last_mut() {
+                    Some(condition) if !(rule == Rule::cond_else && condition.otherwise) => {
+                        condition
+                    }
+                    _ => return Err(Error::Unmatched(keyword.into(), ".if".into())),
+                };
+                if rule == Rule::cond_endif {
+                    self.conditions.pop();
+                } else {
+                    condition.active = !condition.taken;
+                    condition.taken = true;
+                    condition.otherwise = true;
+                }
+                return Ok(());
+            }
+            // Conditions in an excluded block are not evaluated, since they
+            // may refer to names it would have defined.
+            _ if !enclosing => Ok(false),
+            _ => {
+                let scope = self.scope(self.items.len());
+                Lookup {
+                    constants: &self.constants,
+                    labels: &self.labels,
+                    scope: &scope,
+                }
+                .condition(p)
+            }
+        };
+        // A block whose condition cannot be evaluated is excluded entirely,
+        // and still matches its `.else` and `.endif`.
+        self.conditions.push(Condition {
+            span,
+            active: matches!(active, Ok(true)),
+            taken: !enclosing || !matches!(active, Ok(false)),
+            otherwise: false,
+        });
+        active.map(|_| ())
+    }
+
+    /// Finds the pair of the statement itself, inside any `elem`, `label` or
+    /// `directive`.
+    fn statement(p: &Pair<'a, Rule>) -> Pair<'a, Rule> {
+        match p.as_rule() {
+            Rule::elem | Rule::label | Rule::directive => {
+                Assembler::statement(&p.clone().into_inner().next().unwrap())
+            }
+            _ => p.clone(),
+        }
+    }
+
+    /// Finds the span of a statement without the whitespace that precedes it.
+    fn statement_span(p: &Pair<'a, Rule>) -> Span<'a> {
+        match p.as_rule() {
+            Rule::elem | Rule::label | Rule::directive | Rule::instruction => {
+                Assembler::statement_span(&p.clone().into_inner().next().unwrap())
+            }
+            _ => p.as_span(),
+        }
+    }
+
     fn parse_item(&mut self, p: Pair<'a, Rule>) -> Result<Item<'a>> {
+        let scope = self.scope(self.items.len());
+        let names = Lookup {
+            constants: &self.constants,
+            labels: &self.labels,
+            scope: &scope,
+        };
+        let mut lookup = |name: &str| names.value(name, 0);
         match p.as_rule() {
-            Rule::label | Rule::elem => self.parse_item(p.into_inner().next().unwrap()),
-            Rule::instruction => Ok(Item::Inst(Instruction::try_from(p)?)),
-            Rule::label_inner => Ok(Item::Label(p.as_str())),
+            Rule::label | Rule::elem | Rule::directive => {
+                self.parse_item(p.into_inner().next().unwrap())
+            }
+            Rule::org => Ok(Item::Org(
+                Expr::try_from(p.into_inner().next().unwrap())?
+                    .eval_bounded(Assembler::WORD_MAX, &mut lookup)?,
+            )),
+            Rule::align => {
+                let align = Expr::try_from(p.into_inner().next().unwrap())?
+                    .eval_bounded(Assembler::WORD_MAX, &mut lookup)?;
+                if align == 0 {
+                    return Err(Error::Eval(".align requires a non-zero alignment".into()));
+                }
+                let count = (u32::from(align) - self.end % u32::from(align)) % u32::from(align);
+                Ok(Item::Fill {
+                    count: count as u16,
+                    value: 0,
+                })
+            }
+            Rule::fill => {
+                let mut inner = p.into_inner();
+                Ok(Item::Fill {
+                    count: Expr::try_from(inner.next().unwrap())?
+                        .eval_bounded(Assembler::WORD_MAX, &mut lookup)?,
+                    value: Expr::try_from(inner.next().unwrap())?
+                        .eval_bounded(Assembler::BYTE_MAX, &mut lookup)?
+                        as u8,
+                })
+            }
+            Rule::instruction => {
+                let spans = InstSpans::parse(&p);
+                Ok(Item::Inst(Instruction::try_from(p)?, spans))
+            }
+            Rule::label_inner | Rule::local_label | Rule::anon_label => {
+                Ok(Item::Label(p.as_str().to_string(), p.as_span()))
+            }
+            Rule::data_byte => Ok(Item::Bytes(Assembler::parse_values(
+                p,
+                Assembler::BYTE_MAX,
+            )?)),
+            Rule::data_word => Ok(Item::Words(Assembler::parse_values(
+                p,
+                Assembler::WORD_MAX,
+            )?)),
+            Rule::const_equ | Rule::const_define => {
+                let mut inner = p.into_inner();
+                let name = inner.next().unwrap().as_span();
+                Ok(Item::Constant(name, inner.next().unwrap().try_into()?))
+            }
+            Rule::incbin => {
+                let path = p.into_inner().next().unwrap();
+                let name = path.as_str().trim_matches('"');
+                let bytes = self.origin.binary(name).ok_or_else(|| Error::Read {
+                    path: name.into(),
+                    error: std::io::ErrorKind::NotFound.into(),
+                })?;
+                if bytes.len() > usize::from(Assembler::WORD_MAX) {
+                    return Err(Error::ExceedBounds(bytes.len() as i64, Assembler::WORD_MAX));
+                }
+                Ok(Item::Binary(bytes.to_vec()))
+            }
+            Rule::sprite_bitmap => {
+                let rows = p
+                    .into_inner()
+                    .map(|row| {
+                        let span = row.as_span();
+                        Assembler::parse_sprite_row(row).map_err(|e| e.at(span))
+                    })
+                    .collect::<Result<Vec<u8>>>()?;
+                if rows.len() as u16 > Assembler::MAX_SPRITE_HEIGHT {
+                    return Err(Error::ExceedBounds(
+                        rows.len() as i64,
+                        Assembler::MAX_SPRITE_HEIGHT,
+                    ));
+                }
+                Ok(Item::Sprite(rows))
+            }
             other => Err(Error::Internal(format!(
                 "Assembler::parse_item recieved a Pair with Rule type {:?}",
                 other
@@ -65,16 +847,790 @@ impl<'a> Assembler<'a> {
         }
     }
 
-    fn resolve_args(&mut self) -> Result<()> {
-        for inst in self.instructions.iter_mut() {
-            if let Some(lbl) = inst.unresolved_arg() {
-                let addr = self
-                    .labels
-                    .get(lbl)
-                    .ok_or_else(|| Error::UnresolvedLabel(lbl.to_string()))?;
-                inst.resolve_arg(*addr)?;
+    fn parse_values(p: Pair<'a, Rule>, max: u16) -> Result<Vec<(Immediate, Span<'a>)>> {
+        p.into_inner()
+            .map(|val| {
+                let span = val.as_span();
+                Ok((Immediate::parse(val, max)?, span))
+            })
+            .collect()
+    }
+
+    fn parse_sprite_row(p: Pair<'a, Rule>) -> Result<u8> {
+        let pixels = p.as_str().trim_matches('"');
+        if pixels.chars().count() != 8 {
+            return Err(Error::SpriteRow(p.as_str().to_string()));
+        }
+        pixels.chars().try_fold(0u8, |row, c| match c {
+            '#' | '1' => Ok((row << 1) | 1),
+            '.' | '0' => Ok(row << 1),
+            _ => Err(Error::SpriteRow(p.as_str().to_string())),
+        })
+    }
+
+    fn resolve_args(&mut self) -> Vec<Error> {
+        let mut errors = Vec::new();
+        // The sprite most recently loaded into I with `LD I, label`, used to
+        // check the height passed to DRW.
+        let mut sprite: Option<(String, u8)> = None;
+        for (index, (item, origin)) in self.items.iter_mut().zip(&self.origins).enumerate() {
+            let scope = Scope {
+                globals: &self.globals,
+                anonymous: &self.anonymous,
+                index,
+            };
+            let names = Lookup {
+                constants: &self.constants,
+                labels: &self.labels,
+                scope: &scope,
+            };
+            let mut lookup = |name: &str| names.value(name, 0);
+            match item {
+                Item::Inst(inst, spans) => {
+                    if let Some(expr) = inst.unresolved_arg() {
+                        let val = match expr.eval(&mut lookup) {
+                            Ok(val) => val,
+                            Err(e) => {
+                                errors.push(origin.locate(e.at(spans.expr())));
+                                continue;
+                            }
+                        };
+                        if let Instruction::LdAddr { .. } | Instruction::LdLong { .. } = inst {
+                            sprite = u16::try_from(val)
+                                .ok()
+                                .and_then(|addr| self.sprites.get(&addr))
+                                .map(|height| (expr.to_string(), *height));
+                        }
+                        if let Err(e) = inst.resolve_arg(val) {
+                            errors.push(origin.locate(e.at(spans.expr())));
+                            continue;
+                        }
+                    } else if let Instruction::LdAddr { .. }
+                    | Instruction::LdLong { .. }
+                    | Instruction::AddI { .. }
+                    | Instruction::LdSprite { .. } = inst
+                    {
+                        sprite = None;
+                    }
+                    if let (Instruction::Drw { nibble, .. }, Some((label, height))) =
+                        (inst, &sprite)
+                    {
+                        match nibble.to_resolved() {
+                            Ok(drawn) if drawn != u16::from(*height) => {
+                                let warning = Warning::SpriteHeight {
+                                    label: label.clone(),
+                                    height: *height,
+                                    drawn: drawn as u8,
+                                };
+                                self.warnings.push(origin.warn(warning, spans.expr()))
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+                Item::Bytes(values) => {
+                    Assembler::resolve_data(values, Assembler::BYTE_MAX, &mut lookup, |e| {
+                        errors.push(origin.locate(e))
+                    })
+                }
+                Item::Words(values) => {
+                    Assembler::resolve_data(values, Assembler::WORD_MAX, &mut lookup, |e| {
+                        errors.push(origin.locate(e))
+                    })
+                }
+                _ => (),
             }
         }
-        Ok(())
+        errors
+    }
+
+    /// Rejects instructions from extensions that `target` does not support.
+    fn check_target(&self, target: Platform) -> Vec<Error> {
+        let mut errors = Vec::new();
+        for (item, origin) in self.items.iter().zip(&self.origins) {
+            if let Item::Inst(inst, spans) = item {
+                match inst.extension() {
+                    Some(extension) if !target.supports(extension) => errors.push(
+                        origin.locate(
+                            Error::Unsupported {
+                                inst: inst.to_string(),
+                                extension,
+                                target,
+                            }
+                            .at(spans.inst),
+                        ),
+                    ),
+                    _ => (),
+                }
+            }
+        }
+        errors
+    }
+
+    /// Warns about instructions whose behaviour on `target` is likely not
+    /// what was intended.
+    fn lint(&mut self, target: Platform) {
+        use Instruction::*;
+        let quirks = target.quirks();
+        // The load or store that last advanced I, if I has not been set since.
+        // Control can reach a label from anywhere, so I is unknown there.
+        let mut advanced: Option<String> = None;
+        let labelled: HashSet<u32> = self.labels.values().map(|addr| u32::from(*addr)).collect();
+        for (index, item) in self.items.iter().enumerate() {
+            if labelled.contains(&self.placements[index].0) {
+                advanced = None;
+            }
+            let (inst, spans) = match item {
+                Item::Inst(inst, spans) => (inst, spans),
+                _ => continue,
+            };
+            // What is wrong, and the operand (if any) it is wrong about.
+            let behaviour = match inst {
+                Shl { dest, src } | Shr { dest, src } if dest != src && !quirks.shift_vy => {
+                    Some((format!("ignores {src} and shifts {dest} in place"), Some(1)))
+                }
+                JpRel { addr } if quirks.jump_vx => addr
+                    .to_resolved()
+                    .ok()
+                    .filter(|addr| addr >> 8 != 0)
+                    .map(|addr| (format!("adds V{:X} instead of V0", addr >> 8), spans.expr)),
+                Or { dest, .. } | And { dest, .. } | Xor { dest, .. }
+                    if *dest == Register::VF && quirks.logic_resets_vf =>
+                {
+                    Some((
+                        "loses its result because VF is reset afterwards".into(),
+                        Some(0),
+                    ))
+                }
+                Drw { .. } | LdBcd { .. } | LdRegDump { .. } | LdRegRead { .. } | AddI { .. } => {
+                    advanced
+                        .take()
+                        .map(|prev| (format!("uses I after '{prev}' advanced it"), None))
+                }
+                SeImm { .. }
+                | SneImm { .. }
+                | SeReg { .. }
+                | SneReg { .. }
+                | Skp { .. }
+                | Sknp { .. }
+                    if target.supports(Extension::XoChip)
+                        && self.data_after(index).starts_with(&[0xF0, 0x00]) =>
+                {
+                    Some((
+                        "skips 4 bytes, because the data after it begins with 0xF000".into(),
+                        None,
+                    ))
+                }
+                _ => None,
+            };
+            match inst {
+                LdAddr { .. } | LdLong { .. } | LdSprite { .. } => advanced = None,
+                LdRegDump { .. } | LdRegRead { .. } if quirks.load_store_increments_i => {
+                    advanced = Some(inst.to_string())
+                }
+                _ => (),
+            }
+            if let Some((behaviour, operand)) = behaviour {
+                let warning = Warning::Quirk {
+                    inst: inst.to_string(),
+                    behaviour,
+                    target,
+                };
+                let span = operand.map_or_else(|| spans.inst, |i| spans.operand(i));
+                self.warnings.push(self.origins[index].warn(warning, span));
+            }
+        }
+    }
+
+    /// The first two bytes placed after the item at `index`, if they are data
+    /// rather than an instruction.
+    fn data_after(&self, index: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for item in &self.items[index + 1..] {
+            match item {
+                Item::Inst(..) | Item::Org(_) => break,
+                item => bytes.extend(item.bytes().unwrap_or_default()),
+            }
+            if bytes.len() >= 2 {
+                break;
+            }
+        }
+        bytes
+    }
+
+    fn resolve_data(
+        values: &mut [(Immediate, Span<'a>)],
+        max: u16,
+        lookup: &mut impl FnMut(&str) -> Result<i64>,
+        mut report: impl FnMut(Error),
+    ) {
+        for (val, span) in values.iter_mut() {
+            if let Some(expr) = val.unresolved() {
+                if let Err(e) = expr
+                    .eval(lookup)
+                    .and_then(|resolved| val.resolve(resolved, max))
+                {
+                    report(e.at(*span));
+                }
+            }
+        }
+    }
+
+    /// The scope of references in the item at `index`.
+    fn scope(&self, index: usize) -> Scope<'_> {
+        Scope {
+            globals: &self.globals,
+            anonymous: &self.anonymous,
+            index,
+        }
+    }
+}
+
+/// The constants and labels a program defines, as conditional directives
+/// and constants see them. The expander and the assembler both evaluate
+/// conditions through this, so that they agree on which blocks are assembled.
+pub(crate) trait Names {
+    /// The full name of the name written in an expression.
+    fn qualify(&self, name: &str) -> String {
+        name.to_string()
+    }
+
+    /// The expression constant `name` is defined as, if it is one.
+    fn constant(&self, name: &str) -> Option<&Expr>;
+
+    /// The number of constants, which no chain of them without a cycle is
+    /// longer than.
+    fn constant_count(&self) -> usize;
+
+    /// The address of label `name`.
+    fn label(&self, name: &str) -> Result<i64>;
+
+    /// Whether `name` is defined, as a constant or a label.
+    fn defined(&self, name: &str) -> Result<bool>;
+
+    /// Finds the value of a constant or label. `depth` counts the constants
+    /// already being evaluated, so a chain longer than the number of
+    /// constants must contain a cycle.
+    fn value(&self, name: &str, depth: usize) -> Result<i64> {
+        let name = self.qualify(name);
+        match self.constant(&name) {
+            Some(_) if depth > self.constant_count() => Err(Error::RecursiveConstant(name)),
+            Some(expr) => expr.eval(&mut |name| self.value(name, depth + 1)),
+            None => self.label(&name),
+        }
+    }
+
+    /// Evaluates the condition of an `.if`, `.ifdef` or `.ifndef`.
+    fn condition(&self, p: Pair<'_, Rule>) -> Result<bool> {
+        let rule = p.as_rule();
+        let inner = p.into_inner().next().unwrap();
+        if rule == Rule::cond_if {
+            Ok(Expr::try_from(inner)?.eval(&mut |name| self.value(name, 0))? != 0)
+        } else {
+            Ok(self.defined(inner.as_str())? == (rule == Rule::cond_ifdef))
+        }
+    }
+}
+
+/// The names an item sees, resolving local and anonymous references in its
+/// scope.
+struct Lookup<'s> {
+    constants: &'s HashMap<String, Expr>,
+    labels: &'s HashMap<String, u16>,
+    scope: &'s Scope<'s>,
+}
+
+impl Names for Lookup<'_> {
+    fn qualify(&self, name: &str) -> String {
+        self.scope.qualify(name)
+    }
+
+    fn constant(&self, name: &str) -> Option<&Expr> {
+        self.constants.get(name)
+    }
+
+    fn constant_count(&self) -> usize {
+        self.constants.len()
+    }
+
+    fn label(&self, name: &str) -> Result<i64> {
+        self.labels
+            .get(name)
+            .map(|addr| i64::from(*addr))
+            .ok_or_else(|| Error::UnresolvedLabel(name.into()))
+    }
+
+    fn defined(&self, name: &str) -> Result<bool> {
+        Ok(self.constants.contains_key(name) || self.labels.contains_key(name))
+    }
+}
+
+/// Whether a label was made up by a front-end, such as for the jumps an Octo
+/// loop assembles to, rather than written in the program. Such labels have a
+/// `#` in their names, which no written label can, and are not exported.
+pub(crate) fn is_synthesized(name: &str) -> bool {
+    name.contains('#')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assert_ok,
+        parser::Parser,
+        test_utils::{assemble_for, options_for},
+    };
+
+    fn build(text: &str) -> Result<Assembler<'_>> {
+        build_located(text).map_err(Error::into_kind)
+    }
+
+    fn build_located(text: &str) -> Result<Assembler<'_>> {
+        match Parser::parse(text) {
+            Ok(iter) => Assembler::build(iter, &Options::default()),
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_comments_do_not_shift_labels() {
+        let text = include_str!("../test_files/comments.asm");
+        let asm = assert_ok!(build(text));
+        assert_eq!(asm.labels.get("start"), Some(&0x200));
+        let mut dest: Vec<u8> = Default::default();
+        assert_ok!(asm.write_bin(&mut dest));
+        assert_eq!(dest, [0x50, 0x10, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn test_data_directives() {
+        let text = include_str!("../test_files/data.asm");
+        let asm = assert_ok!(build(text));
+        assert_eq!(asm.labels.get("table"), Some(&0x202));
+        assert_eq!(asm.labels.get("words"), Some(&0x205));
+        assert_eq!(asm.labels.get("after"), Some(&0x20B));
+        let mut dest: Vec<u8> = Default::default();
+        assert_ok!(asm.write_bin(&mut dest));
+        assert_eq!(
+            dest,
+            [0x12, 0x0B, 0xF0, 0x90, 0x0F, 0x12, 0x34, 0x00, 0x01, 0xBE, 0xEF, 0x12, 0x0B]
+        );
+    }
+
+    #[test]
+    fn test_sprite_directive() {
+        let text = include_str!("../test_files/sprites.asm");
+        let asm = assert_ok!(build(text));
+        assert_eq!(asm.labels.get("box"), Some(&0x208));
+        assert_eq!(asm.labels.get("bar"), Some(&0x20C));
+        let mut dest: Vec<u8> = Default::default();
+        assert_ok!(asm.write_bin(&mut dest));
+        assert_eq!(&dest[8..], [0xFF, 0x81, 0x81, 0xFF, 0xF0]);
+        assert_eq!(asm.warnings().len(), 1);
+        let warning = &asm.warnings()[0];
+        assert!(matches!(
+            warning.clone().into_kind(),
+            Warning::SpriteHeight { label, height: 1, drawn: 2 } if label == "bar"
+        ));
+        assert!(
+            warning
+                .to_string()
+                .contains("draws 2 rows of sprite 'bar', which is 1 row tall"),
+            "{warning}"
+        );
+        // The warning points at the height drawn.
+        assert_eq!(warning.line_col(), Some((4, 17)));
+    }
+
+    #[test]
+    fn test_quirk_lints() {
+        let text = "
+            SHR V1, V2
+            SHL V3
+            OR VF, V1
+            LD I, V1
+            LD B, V0
+            LD I, 0x300
+            LD I, V1
+            LD I, 0x300
+            DRW V0, V1, 1
+            JP V0, 0x312
+        ";
+        let lints = |target| {
+            let options = Options {
+                target,
+                ..Options::default()
+            };
+            let parsed = match Parser::parse(text) {
+                Ok(iter) => iter,
+                Err(e) => panic!("{:?}", e),
+            };
+            let asm = assert_ok!(Assembler::build(parsed, &options));
+            asm.warnings()
+                .iter()
+                .map(|w| match w.clone().into_kind() {
+                    Warning::Quirk { .. } => w.line_col().unwrap(),
+                    other => panic!("{other}"),
+                })
+                .collect::<Vec<_>>()
+        };
+        // Each warning points at the operand it is about, if there is one.
+        assert_eq!(lints(Platform::Modern), [(2, 21)]);
+        assert_eq!(lints(Platform::Vip), [(4, 16), (6, 13)]);
+        assert_eq!(lints(Platform::Chip48), [(2, 21), (6, 13), (11, 20)]);
+        assert_eq!(lints(Platform::XoChip), [(6, 13)]);
+
+        // I is not known to have been advanced at a label, which may be
+        // reached from elsewhere.
+        let text = "LD I, 0x300\nLD I, V1\nloop:\nDRW V0, V1, 1\nJP loop\n";
+        let parsed = Parser::parse(text).unwrap_or_else(|e| panic!("{e}"));
+        let asm = assert_ok!(Assembler::build(parsed, &options_for(Platform::Vip)));
+        assert!(asm.warnings().is_empty(), "{:?}", asm.warnings());
+    }
+
+    #[test]
+    fn test_schip_target() {
+        let text = "HIGH\nLD HF, V1\nDRW V0, V1, SIZE\nSIZE equ 0\nDRW V0, V1, 1\n";
+        let parsed = match Parser::parse(text) {
+            Ok(iter) => iter,
+            Err(e) => panic!("{:?}", e),
+        };
+        let errors = match Assembler::build(parsed, &Options::default()) {
+            Err(Error::Multiple(errors)) => errors,
+            other => panic!("{other:?}"),
+        };
+        let lines: Vec<_> = errors.iter().map(|e| e.line_col().unwrap().0).collect();
+        assert_eq!(lines, [1, 2, 3]);
+        assert!(
+            errors[2].to_string().contains("SUPER-CHIP"),
+            "{}",
+            errors[2]
+        );
+
+        assert_eq!(
+            assemble_for(text, Platform::Schip),
+            [0x00, 0xFF, 0xF1, 0x30, 0xD0, 0x10, 0xD0, 0x11]
+        );
+    }
+
+    #[test]
+    fn test_xochip_target() {
+        let text = include_str!("../test_files/xochip.asm");
+        let options = options_for(Platform::XoChip);
+        let parsed = Parser::parse(text).unwrap_or_else(|e| panic!("{e}"));
+        let asm = assert_ok!(Assembler::build(parsed, &options));
+        assert_eq!(asm.labels.get("sprite"), Some(&0x1200));
+        assert_eq!(asm.labels.get("end"), Some(&0x216));
+        let mut dest: Vec<u8> = Default::default();
+        assert_ok!(asm.write_bin(&mut dest));
+        assert_eq!(
+            dest[..0x18],
+            [
+                0xF3, 0x01, 0xF0, 0x00, 0x12, 0x00, 0x30, 0x00, 0xF0, 0x00, 0x12, 0x00, 0x51, 0x42,
+                0x54, 0x13, 0xF0, 0x02, 0xF2, 0x3A, 0x00, 0xD2, 0x12, 0x16
+            ]
+        );
+        assert_eq!(dest.len(), 0x1002);
+        assert!(matches!(
+            build("LD I, LONG 0x300"),
+            Err(Error::Unsupported { .. })
+        ));
+
+        let text = "SE V0, 0\ndb 0xF0, 0\nSNE V0, 0\ndw 0xF000\nSKP V0\nLD I, LONG 0x300\n";
+        let parsed = Parser::parse(text).unwrap_or_else(|e| panic!("{e}"));
+        let asm = assert_ok!(Assembler::build(parsed, &options));
+        let lines: Vec<_> = asm
+            .warnings()
+            .iter()
+            .map(|w| match w.clone().into_kind() {
+                Warning::Quirk { .. } => w.line_col().unwrap().0,
+                other => panic!("{other:?}"),
+            })
+            .collect();
+        assert_eq!(lines, [1, 3]);
+    }
+
+    #[test]
+    fn test_local_labels() {
+        let text = include_str!("../test_files/locals.asm");
+        let parsed = Parser::parse(text).unwrap_or_else(|e| panic!("{e}"));
+        let asm = assert_ok!(Assembler::build(parsed, &Options::default()));
+        let mut dest: Vec<u8> = Default::default();
+        assert_ok!(asm.write_bin(&mut dest));
+        assert_eq!(
+            dest,
+            [
+                0x60, 0x00, 0x70, 0x01, 0x30, 0x03, 0x12, 0x02, 0x12, 0x08, 0x12, 0x02, 0x12, 0x0C,
+                0x12, 0x12, 0x12, 0x14, 0x00, 0xE0, 0x12, 0x12
+            ]
+        );
+        assert_eq!(asm.labels["second.loop"], 0x208);
+
+        let cases = [
+            (
+                "first:\nJP first.missing",
+                "Label 'first.missing' is not defined",
+            ),
+            (
+                "first:\nJP .missing",
+                "Label 'first.missing' is not defined",
+            ),
+            ("@@:\nJP +", "Label '+' is not defined"),
+            ("JP -\n@@:", "Label '-' is not defined"),
+        ];
+        for (text, message) in cases {
+            let parsed = Parser::parse(text).unwrap_or_else(|e| panic!("{e}"));
+            let e = Assembler::build(parsed, &Options::default()).unwrap_err();
+            assert!(e.to_string().contains(message), "{text:?}: {e}");
+        }
+    }
+
+    #[test]
+    fn test_listing() {
+        let text = "start:\n    LD V0, 1 ; set\n    db 1, 2, 3, 4, 5\nend:\n    JP start\n";
+        let parsed = Parser::parse(text).unwrap_or_else(|e| panic!("{e}"));
+        let asm = assert_ok!(Assembler::build(parsed, &Options::default()));
+        let mut dest: Vec<u8> = Default::default();
+        assert_ok!(asm.write_listing(&mut dest));
+        assert_eq!(
+            String::from_utf8(dest).unwrap(),
+            "\
+0200               1    start:
+0200  60 01        2        LD V0, 1 ; set
+0202  01 02 03 04  3        db 1, 2, 3, 4, 5
+0206  05
+0207               4    end:
+0207  12 00        5        JP start
+
+Symbols:
+0200  start
+0207  end
+"
+        );
+    }
+
+    #[test]
+    fn test_conditions() {
+        let text = include_str!("../test_files/conditions.asm");
+        let variant = |defines: &[(&str, i64)]| {
+            let options = Options {
+                defines: defines.iter().map(|(n, v)| (n.to_string(), *v)).collect(),
+                ..Options::default()
+            };
+            let parsed = Parser::parse(text).unwrap_or_else(|e| panic!("{e}"));
+            let asm = assert_ok!(Assembler::build(parsed, &options));
+            let mut dest: Vec<u8> = Default::default();
+            assert_ok!(asm.write_bin(&mut dest));
+            (dest, asm.labels.get("trace").copied(), asm.labels["end"])
+        };
+        assert_eq!(variant(&[]), (vec![0x70, 0x01, 0x12, 0x00], None, 0x204));
+        assert_eq!(
+            variant(&[("DEBUG", 1)]),
+            (
+                vec![0x22, 0x06, 0x70, 0x01, 0x12, 0x00, 0x61, 0x01, 0x00, 0xEE],
+                Some(0x206),
+                0x20A
+            )
+        );
+        assert_eq!(variant(&[("LEVEL", 3)]).0, [0x70, 0x03, 0x12, 0x00]);
+
+        let cases = [
+            ".if 1\nCLS",
+            ".endif",
+            ".if 0\n.else\n.else\n.endif",
+            ".if LATER\n.endif\nLATER equ 1",
+        ];
+        for text in cases {
+            assert!(
+                matches!(
+                    build(text),
+                    Err(Error::Unclosed(_) | Error::Unmatched(..) | Error::UnresolvedLabel(_))
+                ),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_sprite_row_width() {
+        assert!(matches!(build("sprite \"#..#\""), Err(Error::SpriteRow(_))));
+    }
+
+    #[test]
+    fn test_constants() {
+        let text = include_str!("../test_files/constants.asm");
+        let asm = assert_ok!(build(text));
+        let mut dest: Vec<u8> = Default::default();
+        assert_ok!(asm.write_bin(&mut dest));
+        assert_eq!(
+            dest,
+            [0x60, 0x03, 0x71, 0x40, 0xA3, 0x00, 0xD0, 0x15, 0x13, 0x00, 0x03, 0x40]
+        );
+
+        // Names that start like a register are not registers.
+        let text = "VALUE equ 3\nVELOCITY equ 2\nVAR equ 0x300\n\
+                    LD V0, VALUE\nSE V1, VELOCITY\nLD I, VAR\nLD VA, VE\n";
+        let asm = assert_ok!(build(text));
+        let mut dest: Vec<u8> = Default::default();
+        assert_ok!(asm.write_bin(&mut dest));
+        assert_eq!(dest, [0x60, 0x03, 0x31, 0x02, 0xA3, 0x00, 0x8A, 0xE0]);
+    }
+
+    #[test]
+    fn test_constant_errors() {
+        let cases = [
+            ("BIG equ 0x10\nDRW V0, V1, BIG", 0xF),
+            ("BIG equ 0x100\nLD V0, BIG", 0xFF),
+            ("BIG equ 0x1000\nJP BIG", 0xFFF),
+        ];
+        for (text, max) in cases {
+            assert!(
+                matches!(build(text), Err(Error::ExceedBounds(_, m)) if m == max),
+                "{text}"
+            );
+        }
+        assert!(matches!(
+            build("A equ 1\n.define A 2"),
+            Err(Error::DuplicateConstant(name)) if name == "A"
+        ));
+    }
+
+    #[test]
+    fn test_expressions() {
+        let text = include_str!("../test_files/expressions.asm");
+        let asm = assert_ok!(build(text));
+        let mut dest: Vec<u8> = Default::default();
+        assert_ok!(asm.write_bin(&mut dest));
+        assert_eq!(
+            dest,
+            [
+                0xA2, 0x10, 0x30, 0x3F, 0x61, 0x0C, 0x62, 0x02, 0x73, 0xFF, 0xD0, 0x17, 0x02, 0x0E,
+                0x00, 0x7E
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expression_errors() {
+        assert!(matches!(
+            build("LD V0, table * 2\ntable:"),
+            Err(Error::ExceedBounds(0x404, 0xFF))
+        ));
+        assert!(matches!(
+            build("A equ B + 1\nB equ A\nLD V0, A"),
+            Err(Error::RecursiveConstant(_))
+        ));
+    }
+
+    #[test]
+    fn test_origin_directives() {
+        let text = include_str!("../test_files/origin.asm");
+        let asm = assert_ok!(build(text));
+        assert_eq!(asm.labels.get("start"), Some(&0x600));
+        assert_eq!(asm.labels.get("aligned"), Some(&0x604));
+        assert_eq!(asm.labels.get("data"), Some(&0x610));
+        let mut dest: Vec<u8> = Default::default();
+        assert_ok!(asm.write_bin(&mut dest));
+        assert_eq!(
+            dest,
+            [
+                0x16, 0x10, 0x01, 0x00, 0xAA, 0xAA, 0xAA, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x02
+            ]
+        );
+    }
+
+    #[test]
+    fn test_origin_errors() {
+        assert!(matches!(
+            build("CLS\n.org 0x100"),
+            Err(Error::OrgBackwards {
+                from: 0x202,
+                to: 0x100
+            })
+        ));
+        // A leading .org may only repeat an origin that is given.
+        let options = Options {
+            origin: Some(0x600),
+            ..Options::default()
+        };
+        let parsed = Parser::parse(".org 0x600\nCLS").unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            assert_ok!(Assembler::build(parsed, &options)).start(),
+            0x600
+        );
+        let parsed = Parser::parse(".org 0x200\nCLS").unwrap_or_else(|e| panic!("{e}"));
+        assert!(matches!(
+            Assembler::build(parsed, &options).map_err(Error::into_kind),
+            Err(Error::OrgConflict {
+                origin: 0x600,
+                org: 0x200
+            })
+        ));
+        assert!(matches!(
+            build(".org 0xFFE\nCLS\nend:\n    CLS"),
+            Err(Error::Overflow { item, addr: 0x1000, memory_size: 0x1000 }) if item == "CLS"
+        ));
+    }
+
+    #[test]
+    fn test_error_locations() {
+        let err = build_located("CLS\n    JP nowhere + 2").unwrap_err();
+        assert!(matches!(err, Error::Located { .. }));
+        let msg = err.with_path("game.asm").to_string();
+        assert!(msg.contains("game.asm:2:8"), "{msg}");
+        assert!(msg.contains("^"), "{msg}");
+        assert!(msg.contains("Label 'nowhere' is not defined"), "{msg}");
+
+        let msg = build_located("LD V0, 0x100").unwrap_err().to_string();
+        assert!(msg.contains("1:8"), "{msg}");
+        let msg = build_located("DRW V0, V1, HEIGHT").unwrap_err().to_string();
+        assert!(msg.contains("1:13"), "{msg}");
+        let msg = build_located("LD I, LONG 0x300").unwrap_err().to_string();
+        assert!(msg.contains("1:1"), "{msg}");
+
+        let msg = build_located("loop:\n    CLS\nloop:")
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("3:1"), "{msg}");
+        assert!(msg.contains("1:1"), "{msg}");
+        assert!(msg.contains("first defined here"), "{msg}");
+    }
+
+    #[test]
+    fn test_multiple_errors() {
+        let err = build_located("JP a\nLD V0, 0x100\nx:\nx:\nJP b").unwrap_err();
+        let errors = match err {
+            Error::Multiple(errors) => errors,
+            other => panic!("{other}"),
+        };
+        let kinds: Vec<_> = errors.into_iter().map(Error::into_kind).collect();
+        assert!(matches!(
+            kinds.as_slice(),
+            [
+                Error::UnresolvedLabel(a),
+                Error::ExceedBounds(0x100, 0xFF),
+                Error::DuplicateLabel(_),
+                Error::UnresolvedLabel(b),
+            ] if a == "a" && b == "b"
+        ));
+
+        // An invalid instruction still takes up the size of its form.
+        let err = build_located("CLS\nLD I, LONG 0x10000\n.org 0x205\n").unwrap_err();
+        let errors = match err {
+            Error::Multiple(errors) => errors,
+            other => panic!("{other}"),
+        };
+        let kinds: Vec<_> = errors.into_iter().map(Error::into_kind).collect();
+        assert!(matches!(
+            kinds.as_slice(),
+            [
+                Error::NumParse(_),
+                Error::OrgBackwards {
+                    from: 0x206,
+                    to: 0x205
+                },
+            ]
+        ));
     }
 }
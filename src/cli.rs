@@ -0,0 +1,224 @@
+//! The `chip8c` command line tool.
+use crate::{
+    args::{Args, Command},
+    assembler::{Assembler, Options},
+    disassembler::Disassembler,
+    emulator::{Emulator, Script},
+    error::*,
+    octo,
+    parser::Syntax,
+    quirks::Platform,
+    source::Source,
+    symbols::SymbolFormat,
+};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Runs the command line tool with the process's arguments.
+pub fn main() -> Result<()> {
+    let args = Args::parse();
+    match &args.command {
+        Some(Command::Disasm {
+            input,
+            output,
+            origin,
+            target,
+        }) => disassemble(input, output.as_deref(), *origin, *target),
+        Some(Command::Run {
+            input,
+            cycles,
+            dump_screen,
+            keys,
+            build,
+        }) => run(
+            input,
+            *cycles,
+            *dump_screen,
+            keys,
+            build.syntax,
+            &build.include,
+            &build.options(),
+        ),
+        None => assemble(&args),
+    }
+}
+
+fn assemble(args: &Args) -> Result<()> {
+    // clap only allows a missing input alongside a subcommand.
+    let input: &PathBuf = args.input.as_ref().unwrap();
+    let options = args.build.options();
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| input.with_extension(args.format.extension()));
+    let sidecars = Sidecars {
+        listing: args.listing.clone(),
+        symbols: args
+            .symbols
+            .iter()
+            .map(|format| (*format, output.with_extension(format.extension())))
+            .collect(),
+        debug_info: args.debug_info.then(|| output.with_extension("debug.json")),
+    };
+    let (start, rom) = build(
+        input,
+        args.build.syntax,
+        &args.build.include,
+        &options,
+        &sidecars,
+    )?;
+    let mut output = io::BufWriter::new(fs::File::create(&output)?);
+    args.format.write(&rom, start, &mut output)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Files written alongside the binary that describe the program.
+#[derive(Debug, Default)]
+struct Sidecars {
+    listing: Option<PathBuf>,
+    symbols: Vec<(SymbolFormat, PathBuf)>,
+    debug_info: Option<PathBuf>,
+}
+
+impl Sidecars {
+    /// Writes the files for `asm`, which was assembled from `input`.
+    fn write(&self, asm: &Assembler<'_>, input: &Path) -> Result<()> {
+        if let Some(path) = &self.listing {
+            asm.write_listing(io::BufWriter::new(fs::File::create(path)?))?;
+        }
+        let symbols = asm.symbols();
+        for (format, path) in &self.symbols {
+            format.write(&symbols, io::BufWriter::new(fs::File::create(path)?))?;
+        }
+        if let Some(path) = &self.debug_info {
+            let program = input.to_string_lossy();
+            asm.write_debug_info(&program, io::BufWriter::new(fs::File::create(path)?))?;
+        }
+        Ok(())
+    }
+}
+
+/// Assembles `input` in `syntax`, or in the syntax its extension implies,
+/// returning the address the ROM is loaded at and its bytes. `include` is searched for files that chip8c assembly
+/// includes.
+fn build(
+    input: &Path,
+    syntax: Option<Syntax>,
+    include: &[PathBuf],
+    options: &Options,
+    sidecars: &Sidecars,
+) -> Result<(u16, Vec<u8>)> {
+    match syntax.unwrap_or_else(|| Syntax::for_path(input)) {
+        Syntax::Chip8c => Source::load(input, include, &options.defines).and_then(|source| {
+            Assembler::build_source(&source, options).and_then(|asm| rom(asm, input, sidecars))
+        }),
+        Syntax::Octo => {
+            // Octo has no includes or conditional assembly for -I and -D to
+            // apply to.
+            if !include.is_empty() {
+                return Err(Error::OctoOption("--include"));
+            }
+            if !options.defines.is_empty() {
+                return Err(Error::OctoOption("--define"));
+            }
+            let text = fs::read_to_string(input)?;
+            octo::lower(&text)
+                .and_then(|items| Assembler::build_items(items, options))
+                .and_then(|asm| rom(asm, input, sidecars))
+                .map_err(|e| e.with_path(&input.to_string_lossy()))
+        }
+    }
+}
+
+/// Prints an assembled program's warnings, writes its sidecar files and
+/// returns its load address and bytes.
+fn rom(asm: Assembler<'_>, input: &Path, sidecars: &Sidecars) -> Result<(u16, Vec<u8>)> {
+    for warning in asm.warnings() {
+        eprintln!("Warning: {warning}");
+    }
+    sidecars.write(&asm, input)?;
+    let mut rom = Vec::new();
+    asm.write_bin(&mut rom)?;
+    Ok((asm.start(), rom))
+}
+
+fn disassemble(input: &Path, output: Option<&Path>, origin: u16, target: Platform) -> Result<()> {
+    let rom = fs::read(input)?;
+    let disasm = Disassembler::build(&rom, origin, target);
+    for warning in disasm.warnings() {
+        eprintln!("Warning: {warning}");
+    }
+    match output {
+        Some(p) => disasm.write_asm(fs::File::create(p)?),
+        None => disasm.write_asm(io::stdout().lock()),
+    }
+}
+
+fn run(
+    input: &Path,
+    cycles: u64,
+    dump_screen: bool,
+    keys: &[(u64, u16)],
+    syntax: Option<Syntax>,
+    include: &[PathBuf],
+    options: &Options,
+) -> Result<()> {
+    // The program is loaded where it was assembled for, which an .org at its
+    // start may have moved from the origin.
+    let (start, rom) = build(input, syntax, include, options, &Sidecars::default())?;
+    let mut emu = Emulator::new(&rom, start, options.target, options.memory_size)?;
+    let mut keypad = Script::new(keys.to_vec());
+    for _ in 0..cycles {
+        emu.step(&mut keypad)?;
+    }
+    if dump_screen {
+        print!("{}", emu.dump_screen());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_ok;
+
+    #[test]
+    fn test_run_from_org() {
+        // The program is assembled for 0x600, so it only loops in place if it
+        // is loaded there rather than at the origin.
+        let input = Path::new("test_files/eti660.asm");
+        let options = Options::default();
+        assert_ok!(run(input, 2000, false, &[], None, &[], &options));
+    }
+
+    #[test]
+    fn test_run_memory_size() {
+        // The emulator has the memory the program was assembled for.
+        let input = Path::new("test_files/high.asm");
+        let options = Options {
+            memory_size: 0x2000,
+            target: Platform::Schip,
+            ..Options::default()
+        };
+        assert_ok!(run(input, 1, false, &[], None, &[], &options));
+    }
+
+    #[test]
+    fn test_octo_rejects_chip8c_options() {
+        let input = Path::new("test_files/octo.8o");
+        let include = [PathBuf::from("test_files/include")];
+        let err = run(input, 1, false, &[], None, &include, &Options::default()).unwrap_err();
+        assert!(matches!(err, Error::OctoOption("--include")), "{err}");
+        let options = Options {
+            defines: vec![("DEBUG".to_string(), 1)],
+            ..Options::default()
+        };
+        let err = run(input, 1, false, &[], None, &[], &options).unwrap_err();
+        assert!(matches!(err, Error::OctoOption("--define")), "{err}");
+        assert_ok!(run(input, 1, false, &[], None, &[], &Options::default()));
+    }
+}
@@ -0,0 +1,371 @@
+use crate::{
+    address::Address, assembler::Options, error::*, expr::Expr, immediate::Immediate,
+    instruction::Instruction, quirks::Platform,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::prelude::*,
+};
+
+/// What a synthesized label points at, in order of precedence when several
+/// instructions target the same address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Target {
+    Subroutine,
+    Jump,
+    Data,
+}
+
+impl Target {
+    fn prefix(self) -> &'static str {
+        match self {
+            Target::Subroutine => "sub",
+            Target::Jump => "loc",
+            Target::Data => "dat",
+        }
+    }
+}
+
+/// Longest run of data bytes written on one `dw` line.
+const BYTES_PER_LINE: usize = 8;
+
+/// Recursive-descent disassembler. Control flow is traced from the origin;
+/// reached words are written as instructions and everything else as data, so
+/// the output reassembles to the same bytes.
+#[derive(Debug)]
+pub struct Disassembler<'a> {
+    rom: &'a [u8],
+    origin: u16,
+    target: Platform,
+    code: BTreeMap<u16, Instruction>,
+    labels: HashMap<u16, Target>,
+    warnings: Vec<Warning>,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn build(rom: &'a [u8], origin: u16, target: Platform) -> Disassembler<'a> {
+        let mut disasm = Disassembler {
+            rom,
+            origin,
+            target,
+            code: BTreeMap::new(),
+            labels: HashMap::new(),
+            warnings: Vec::new(),
+        };
+        let reached = disasm.trace();
+        // Instructions can overlap if a jump lands mid-word. Keep the first of
+        // each overlapping pair; the other's bytes are written by it anyway.
+        let mut next = 0;
+        for addr in reached {
+            if u32::from(addr) >= next {
+                let inst = disasm.instruction(disasm.code_at(addr).unwrap()).unwrap();
+                next = u32::from(addr) + u32::from(inst.size());
+                disasm.code.insert(addr, inst);
+            }
+        }
+        for inst in disasm.code.values() {
+            if let Some((addr, kind)) = Disassembler::target(inst) {
+                if disasm.is_line(addr) {
+                    let entry = disasm.labels.entry(addr).or_insert(kind);
+                    *entry = kind.min(*entry);
+                }
+            }
+        }
+        disasm
+    }
+
+    pub fn write_asm(&self, mut dest: impl Write) -> Result<()> {
+        if self.origin != Options::DEFAULT_ORIGIN {
+            writeln!(dest, ".org {:#X}", self.origin)?;
+        }
+        let mut offset = 0;
+        while offset < self.rom.len() {
+            let addr = self.addr(offset);
+            if let Some(label) = addr.and_then(|a| self.label(a)) {
+                writeln!(dest, "{label}:")?;
+            }
+            if let Some(inst) = addr.and_then(|a| self.decode(a)) {
+                writeln!(dest, "    {inst}")?;
+                offset += usize::from(inst.size());
+                continue;
+            }
+            // Data runs until the next instruction or label.
+            let mut end = offset + 1;
+            while end < self.rom.len() && end - offset < BYTES_PER_LINE {
+                match self.addr(end) {
+                    Some(a) if self.code.contains_key(&a) || self.labels.contains_key(&a) => break,
+                    _ => end += 1,
+                }
+            }
+            // Data is written as words, with a trailing odd byte on its own.
+            let (words, odd) = self.rom[offset..end].split_at((end - offset) & !1);
+            if !words.is_empty() {
+                let words: Vec<String> = words
+                    .chunks(2)
+                    .map(|w| format!("{:#06X}", u16::from_be_bytes([w[0], w[1]])))
+                    .collect();
+                writeln!(dest, "    dw {}", words.join(", "))?;
+            }
+            if let [byte] = odd {
+                writeln!(dest, "    db {byte:#04X}")?;
+            }
+            offset = end;
+        }
+        Ok(())
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Follows jumps, calls, skips and returns from the origin, returning the
+    /// address of every instruction that can be reached.
+    fn trace(&mut self) -> BTreeSet<u16> {
+        use Instruction::*;
+        let mut reached = BTreeSet::new();
+        let mut pending = vec![self.origin];
+        while let Some(addr) = pending.pop() {
+            if reached.contains(&addr) {
+                continue;
+            }
+            let code = match self.code_at(addr) {
+                Some(code) => code,
+                None => continue,
+            };
+            let inst = match self.instruction(code) {
+                Some(inst) => inst,
+                None => {
+                    self.warnings.push(Warning::InvalidInstruction {
+                        addr,
+                        word: u16::from_be_bytes([code[0], code[1]]),
+                    });
+                    continue;
+                }
+            };
+            reached.insert(addr);
+            let next = addr.checked_add(inst.size());
+            let target = Disassembler::target(&inst).map(|(target, _)| target);
+            match inst {
+                Ret | Exit => (),
+                JpAbs { .. } => pending.extend(target),
+                Call { .. } => pending.extend(target.into_iter().chain(next)),
+                JpRel { .. } => self.warnings.push(Warning::IndirectJump {
+                    addr,
+                    target: target.unwrap_or_default(),
+                }),
+                SeImm { .. }
+                | SneImm { .. }
+                | SeReg { .. }
+                | SneReg { .. }
+                | Skp { .. }
+                | Sknp { .. } => {
+                    // A skip passes over the whole of the next instruction,
+                    // which is four bytes for `LD I, LONG`.
+                    let skipped = next.and_then(|n| n.checked_add(self.size_at(n)));
+                    pending.extend(next.into_iter().chain(skipped))
+                }
+                _ => pending.extend(next),
+            }
+        }
+        reached
+    }
+
+    /// Decodes the start of `code` as an instruction that the target supports.
+    fn instruction(&self, code: &[u8]) -> Option<Instruction> {
+        Instruction::from_bytes(code).filter(|inst| {
+            inst.extension()
+                .is_none_or(|extension| self.target.supports(extension))
+        })
+    }
+
+    /// Returns a reached instruction, with its address replaced by a label if
+    /// one was made.
+    fn decode(&self, addr: u16) -> Option<Instruction> {
+        use Instruction::*;
+        let mut inst = self.code.get(&addr)?.clone();
+        match &mut inst {
+            JpAbs { addr } | Call { addr } | JpRel { addr } | LdAddr { addr } => {
+                if let Some(label) = self.label(addr.to_resolved().ok()?) {
+                    *addr = Address::Expr(Expr::Symbol(label));
+                }
+            }
+            LdLong { addr } => {
+                if let Some(label) = self.label(addr.to_resolved().ok()?) {
+                    *addr = Immediate::Expr(Expr::Symbol(label));
+                }
+            }
+            _ => (),
+        }
+        Some(inst)
+    }
+
+    fn label(&self, addr: u16) -> Option<String> {
+        let kind = self.labels.get(&addr)?;
+        Some(format!("{}_{addr:03X}", kind.prefix()))
+    }
+
+    fn addr(&self, offset: usize) -> Option<u16> {
+        u16::try_from(offset)
+            .ok()
+            .and_then(|offset| self.origin.checked_add(offset))
+    }
+
+    /// The bytes from `addr` to the end of the ROM, if there is at least a
+    /// word of them.
+    fn code_at(&self, addr: u16) -> Option<&'a [u8]> {
+        let offset = usize::from(addr.checked_sub(self.origin)?);
+        self.rom.get(offset..).filter(|code| code.len() >= 2)
+    }
+
+    /// Size of the instruction at `addr`, treating anything undecodable as a
+    /// single word.
+    fn size_at(&self, addr: u16) -> u16 {
+        self.code_at(addr)
+            .and_then(|code| self.instruction(code))
+            .map_or(2, |inst| inst.size())
+    }
+
+    /// Whether `addr` can start an output line, and so can be labelled. Only
+    /// the bytes after the first of an instruction cannot.
+    fn is_line(&self, addr: u16) -> bool {
+        let in_rom = addr
+            .checked_sub(self.origin)
+            .is_some_and(|offset| usize::from(offset) < self.rom.len());
+        let mid_inst = self
+            .code
+            .range(..addr)
+            .next_back()
+            .is_some_and(|(start, inst)| u32::from(*start) + u32::from(inst.size()) > addr.into());
+        in_rom && !mid_inst
+    }
+
+    fn target(inst: &Instruction) -> Option<(u16, Target)> {
+        use Instruction::*;
+        let (addr, kind) = match inst {
+            Call { addr } => (addr.to_resolved(), Target::Subroutine),
+            JpAbs { addr } | JpRel { addr } => (addr.to_resolved(), Target::Jump),
+            LdAddr { addr } => (addr.to_resolved(), Target::Data),
+            LdLong { addr } => (addr.to_resolved(), Target::Data),
+            _ => return None,
+        };
+        Some((addr.ok()?, kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assert_ok, test_utils::assemble_for as assemble};
+
+    fn disassemble(rom: &[u8], origin: u16) -> String {
+        disassemble_for(rom, origin, Platform::Modern)
+    }
+
+    fn disassemble_for(rom: &[u8], origin: u16, target: Platform) -> String {
+        let mut dest = Vec::new();
+        assert_ok!(Disassembler::build(rom, origin, target).write_asm(&mut dest));
+        String::from_utf8(dest).unwrap()
+    }
+
+    #[test]
+    fn test_decode_every_word() {
+        for word in 0..=u16::MAX {
+            let bytes = word.to_be_bytes();
+            if let Some(inst) = Instruction::from_bytes(&bytes) {
+                assert_eq!(assert_ok!(inst.as_bytes()), bytes, "{inst}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let progs = [
+            (
+                include_str!("../test_files/instructions.asm"),
+                Platform::Modern,
+            ),
+            (include_str!("../test_files/labels.asm"), Platform::Modern),
+            (include_str!("../test_files/data.asm"), Platform::Modern),
+            (include_str!("../test_files/sprites.asm"), Platform::Modern),
+            (include_str!("../test_files/schip.asm"), Platform::Schip),
+            (include_str!("../test_files/xochip.asm"), Platform::XoChip),
+        ];
+        for (text, target) in progs {
+            let rom = assemble(text, target);
+            let text = disassemble_for(&rom, 0x200, target);
+            assert_eq!(assemble(&text, target), rom);
+        }
+        let schip = assemble(include_str!("../test_files/schip.asm"), Platform::Schip);
+        assert!(disassemble_for(&schip, 0x200, Platform::Schip).contains("LD HF, V0"));
+        assert!(!disassemble(&schip, 0x200).contains("HIGH"));
+        // Every possible word, in chunks small enough to fit in memory.
+        let words: Vec<u8> = (0..=u16::MAX).flat_map(u16::to_be_bytes).collect();
+        for rom in words.chunks(0xC00) {
+            let text = disassemble(rom, 0x200);
+            assert_eq!(assemble(&text, Platform::Modern), rom);
+        }
+        let odd = [0x12, 0x02, 0xFF];
+        assert_eq!(assemble(&disassemble(&odd, 0x200), Platform::Modern), odd);
+        // Data is words, with a byte left over at the end.
+        let data = [0x12, 0x00, 0xF0, 0xFF, 0xAB];
+        let text = disassemble(&data, 0x200);
+        assert_eq!(
+            text,
+            "loc_200:\n    JP loc_200\n    dw 0xF0FF\n    db 0xAB\n"
+        );
+        assert_eq!(assemble(&text, Platform::Modern), data);
+    }
+
+    #[test]
+    fn test_trace() {
+        let rom = [
+            0x22, 0x08, // CALL sub_208
+            0xA2, 0x0E, // LD I, dat_20E
+            0x12, 0x00, // JP loc_200
+            0xFF, 0xFF, // unreached
+            0x30, 0x01, // SE V0, 0x1
+            0xB2, 0x00, // JP V0, loc_200
+            0x00, 0xEE, // RET
+            0xF0, 0x90, 0x00, 0xE0, // sprite
+        ];
+        let disasm = Disassembler::build(&rom, 0x200, Platform::Modern);
+        assert!(matches!(
+            disasm.warnings(),
+            [Warning::IndirectJump {
+                addr: 0x20A,
+                target: 0x200
+            }]
+        ));
+        assert_eq!(
+            disassemble(&rom, 0x200),
+            "loc_200:\n    CALL sub_208\n    LD I, dat_20E\n    JP loc_200\n    dw 0xFFFF\n\
+             sub_208:\n    SE V0, 0x1\n    JP V0, loc_200\n    RET\n\
+             dat_20E:\n    dw 0xF090, 0x00E0\n"
+        );
+    }
+
+    #[test]
+    fn test_trace_alignment() {
+        let rom = [0x12, 0x03, 0x00, 0x00, 0xE0, 0x12, 0x03];
+        assert_eq!(
+            disassemble(&rom, 0x200),
+            "    JP loc_203\n    db 0x00\nloc_203:\n    CLS\n    JP loc_203\n"
+        );
+        let text = disassemble(&[0x16, 0x02, 0x00, 0xE0], 0x600);
+        assert_eq!(text, ".org 0x600\n    JP loc_602\nloc_602:\n    CLS\n");
+        // Skipping over LD I, LONG lands four bytes on.
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x02, 0x08, 0x00, 0xE0, 0x00, 0xFD];
+        assert_eq!(
+            disassemble_for(&rom, 0x200, Platform::XoChip),
+            "    SE V0, 0x0\n    LD I, LONG dat_208\n    CLS\ndat_208:\n    EXIT\n"
+        );
+        let disasm = Disassembler::build(&[0xFF, 0xFF], 0x200, Platform::Modern);
+        assert!(matches!(
+            disasm.warnings(),
+            [Warning::InvalidInstruction {
+                addr: 0x200,
+                word: 0xFFFF
+            }]
+        ));
+    }
+}
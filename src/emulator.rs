@@ -0,0 +1,723 @@
+use crate::{
+    error::*,
+    instruction::Instruction,
+    quirks::{Platform, Quirks},
+    register::Register,
+};
+use std::fmt::Write;
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+const STACK_SIZE: usize = 16;
+/// XO-CHIP's two bitplanes.
+const PLANES: usize = 2;
+/// Instructions executed per 60Hz timer tick.
+const CYCLES_PER_FRAME: u64 = 10;
+const FONT_ADDR: u16 = 0x50;
+#[rustfmt::skip]
+const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+const BIG_FONT_ADDR: u16 = 0xA0;
+/// SUPER-CHIP's 8x10 digits for `LD HF, Vx`.
+#[rustfmt::skip]
+const BIG_FONT: [u8; 100] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+/// Supplies the state of the hex keypad to the emulator.
+pub trait Keypad {
+    /// Returns the keys held down on `cycle`, with bit `n` set for key `n`.
+    fn keys(&mut self, cycle: u64) -> u16;
+}
+
+/// Plays back a fixed list of keypad states. Each state is held from its
+/// cycle until the next one; no keys are held before the first.
+#[derive(Debug, Default)]
+pub struct Script {
+    events: Vec<(u64, u16)>,
+}
+
+impl Script {
+    pub fn new(mut events: Vec<(u64, u16)>) -> Script {
+        events.sort_by_key(|(cycle, _)| *cycle);
+        Script { events }
+    }
+}
+
+impl Keypad for Script {
+    fn keys(&mut self, cycle: u64) -> u16 {
+        self.events
+            .iter()
+            .take_while(|(start, _)| *start <= cycle)
+            .last()
+            .map_or(0, |(_, keys)| *keys)
+    }
+}
+
+#[derive(Debug)]
+pub struct Emulator {
+    quirks: Quirks,
+    memory: Vec<u8>,
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    stack: Vec<u16>,
+    dt: u8,
+    st: u8,
+    keys: u16,
+    /// RPL user flags, saved and restored by `LD R, Vx` and `LD Vx, R`.
+    rpl: [u8; 16],
+    /// One bit per pixel, most significant first. Only the top-left 64x32
+    /// pixels are used in low resolution.
+    screen: [[u128; HIRES_HEIGHT]; PLANES],
+    /// The bitplanes that drawing, clearing and scrolling act on, one bit
+    /// each, as set by `PLANE n`.
+    plane: u8,
+    /// XO-CHIP's 1-bit audio pattern and playback pitch.
+    pattern: [u8; 16],
+    pitch: u8,
+    hires: bool,
+    halted: bool,
+    cycle: u64,
+    /// Whether a sprite has been drawn this frame, for `Quirks::display_wait`.
+    drawn: bool,
+    rng: u64,
+}
+
+impl Emulator {
+    /// Loads `program` at `origin` in `memory_size` bytes of memory, to run
+    /// it as `target` would.
+    pub fn new(
+        program: &[u8],
+        origin: u16,
+        target: Platform,
+        memory_size: u32,
+    ) -> Result<Emulator> {
+        let start = usize::from(origin);
+        let end = start + program.len();
+        if end > memory_size as usize {
+            return Err(Error::Overflow {
+                item: "program".into(),
+                addr: origin.into(),
+                memory_size,
+            });
+        }
+        let mut memory = vec![0; memory_size as usize];
+        // Fonts that do not fit in a tiny memory are left out.
+        for (addr, font) in [(FONT_ADDR, &FONT[..]), (BIG_FONT_ADDR, &BIG_FONT[..])] {
+            let addr = usize::from(addr);
+            if let Some(dest) = memory.get_mut(addr..addr + font.len()) {
+                dest.copy_from_slice(font);
+            }
+        }
+        memory[start..end].copy_from_slice(program);
+        Ok(Emulator {
+            quirks: target.quirks(),
+            memory,
+            v: [0; 16],
+            i: 0,
+            pc: origin,
+            stack: Vec::with_capacity(STACK_SIZE),
+            dt: 0,
+            st: 0,
+            keys: 0,
+            rpl: [0; 16],
+            screen: [[0; HIRES_HEIGHT]; PLANES],
+            plane: 1,
+            pattern: [0; 16],
+            pitch: 64,
+            hires: false,
+            halted: false,
+            cycle: 0,
+            drawn: false,
+            rng: 0x2545_F491_4F6C_DD1D,
+        })
+    }
+
+    /// Fetches, decodes and executes the instruction at the program counter,
+    /// then ticks the timers if a frame has passed. Does nothing once the
+    /// program has run `EXIT`.
+    pub fn step(&mut self, keypad: &mut impl Keypad) -> Result<()> {
+        if self.halted {
+            return Ok(());
+        }
+        self.keys = keypad.keys(self.cycle);
+        let bytes = self.memory(self.pc, 2)?;
+        let word = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let inst =
+            Instruction::from_bytes(self.code_at(self.pc)).ok_or(Error::InvalidInstruction {
+                addr: self.pc,
+                word,
+            })?;
+        self.pc = self.pc.wrapping_add(inst.size());
+        self.execute(&inst)?;
+        self.cycle += 1;
+        if self.cycle.is_multiple_of(CYCLES_PER_FRAME) {
+            self.dt = self.dt.saturating_sub(1);
+            self.st = self.st.saturating_sub(1);
+            self.drawn = false;
+        }
+        Ok(())
+    }
+
+    /// Executes `inst` as though it had just been fetched, i.e. with the
+    /// program counter already pointing at the following instruction.
+    pub fn execute(&mut self, inst: &Instruction) -> Result<()> {
+        use Instruction::*;
+        let pc = self.pc.wrapping_sub(inst.size());
+        match inst {
+            Cls => self.planes().for_each(|screen| screen.fill(0)),
+            Ret => self.pc = self.stack.pop().ok_or(Error::StackUnderflow(pc))?,
+            Sys { .. } => (),
+            JpAbs { addr } => self.pc = addr.to_resolved()?,
+            Call { addr } => {
+                if self.stack.len() == STACK_SIZE {
+                    return Err(Error::StackOverflow(pc));
+                }
+                self.stack.push(self.pc);
+                self.pc = addr.to_resolved()?;
+            }
+            SeImm { reg, imm } => self.skip_if(self.v(*reg) == imm.to_resolved()? as u8),
+            SneImm { reg, imm } => self.skip_if(self.v(*reg) != imm.to_resolved()? as u8),
+            SeReg { reg0, reg1 } => self.skip_if(self.v(*reg0) == self.v(*reg1)),
+            SneReg { reg0, reg1 } => self.skip_if(self.v(*reg0) != self.v(*reg1)),
+            LdImm { reg, imm } => self.set(*reg, imm.to_resolved()? as u8),
+            AddImm { reg, imm } => {
+                self.set(*reg, self.v(*reg).wrapping_add(imm.to_resolved()? as u8))
+            }
+            LdReg { dest, src } => self.set(*dest, self.v(*src)),
+            Or { dest, src } => self.logic(*dest, self.v(*dest) | self.v(*src)),
+            And { dest, src } => self.logic(*dest, self.v(*dest) & self.v(*src)),
+            Xor { dest, src } => self.logic(*dest, self.v(*dest) ^ self.v(*src)),
+            // VF is written last so that it holds the flag even if it is `dest`.
+            AddReg { dest, src } => {
+                let (val, carry) = self.v(*dest).overflowing_add(self.v(*src));
+                self.set_with_flag(*dest, val, carry)
+            }
+            Sub { dest, src } => {
+                let (val, borrow) = self.v(*dest).overflowing_sub(self.v(*src));
+                self.set_with_flag(*dest, val, !borrow)
+            }
+            SubN { dest, src } => {
+                let (val, borrow) = self.v(*src).overflowing_sub(self.v(*dest));
+                self.set_with_flag(*dest, val, !borrow)
+            }
+            Shr { dest, src } => {
+                let val = self.v(if self.quirks.shift_vy { *src } else { *dest });
+                self.set_with_flag(*dest, val >> 1, val & 1 == 1)
+            }
+            Shl { dest, src } => {
+                let val = self.v(if self.quirks.shift_vy { *src } else { *dest });
+                self.set_with_flag(*dest, val << 1, val >> 7 == 1)
+            }
+            LdAddr { addr } => self.i = addr.to_resolved()?,
+            JpRel { addr } => {
+                let addr = addr.to_resolved()?;
+                let reg = match self.quirks.jump_vx {
+                    true => Register::try_from((addr >> 8) as u8 & 0xF)?,
+                    false => Register::V0,
+                };
+                self.pc = addr.wrapping_add(self.v(reg).into())
+            }
+            Rnd { reg, imm } => {
+                // xorshift64, so runs are reproducible.
+                self.rng ^= self.rng << 13;
+                self.rng ^= self.rng >> 7;
+                self.rng ^= self.rng << 17;
+                self.set(*reg, self.rng as u8 & imm.to_resolved()? as u8)
+            }
+            Drw { .. } if self.quirks.display_wait && self.drawn => self.pc = pc,
+            Drw { x, y, nibble } => self.draw(*x, *y, nibble.to_resolved()?)?,
+            Skp { reg } => self.skip_if(self.key_down(*reg)),
+            Sknp { reg } => self.skip_if(!self.key_down(*reg)),
+            LdReadDt { reg } => self.set(*reg, self.dt),
+            LdKey { reg } => match self.keys {
+                0 => self.pc = pc,
+                keys => self.set(*reg, keys.trailing_zeros() as u8),
+            },
+            LdSetDt { reg } => self.dt = self.v(*reg),
+            LdSetSt { reg } => self.st = self.v(*reg),
+            AddI { reg } => self.i = self.i.wrapping_add(self.v(*reg).into()),
+            LdSprite { reg } => self.i = FONT_ADDR + u16::from(self.v(*reg) & 0xF) * 5,
+            LdBcd { reg } => {
+                let val = self.v(*reg);
+                self.memory(self.i, 3)?
+                    .copy_from_slice(&[val / 100, val / 10 % 10, val % 10]);
+            }
+            LdRegDump { reg } => {
+                let count = *reg as usize + 1;
+                let regs = self.v;
+                self.memory(self.i, count)?.copy_from_slice(&regs[..count]);
+                self.advance_i(count);
+            }
+            LdRegRead { reg } => {
+                let count = *reg as usize + 1;
+                let mut regs = [0; 16];
+                regs[..count].copy_from_slice(self.memory(self.i, count)?);
+                self.v[..count].copy_from_slice(&regs[..count]);
+                self.advance_i(count);
+            }
+            Scd { nibble } => {
+                let (_, height) = self.size();
+                let n = usize::from(nibble.to_resolved()?).min(height);
+                for screen in self.planes() {
+                    screen.copy_within(0..height - n, n);
+                    screen[..n].fill(0);
+                }
+            }
+            Scu { nibble } => {
+                let (_, height) = self.size();
+                let n = usize::from(nibble.to_resolved()?).min(height);
+                for screen in self.planes() {
+                    screen.copy_within(n..height, 0);
+                    screen[height - n..height].fill(0);
+                }
+            }
+            Scr => {
+                let mask = self.row_mask();
+                self.planes()
+                    .flatten()
+                    .for_each(|row| *row = (*row >> 4) & mask);
+            }
+            Scl => self.planes().flatten().for_each(|row| *row <<= 4),
+            Exit => self.halted = true,
+            Low => self.hires = false,
+            High => self.hires = true,
+            LdBigSprite { reg } => self.i = BIG_FONT_ADDR + u16::from(self.v(*reg) & 0xF) * 10,
+            LdRplDump { reg } => {
+                let count = *reg as usize + 1;
+                self.rpl[..count].copy_from_slice(&self.v[..count]);
+            }
+            LdRplRead { reg } => {
+                let count = *reg as usize + 1;
+                self.v[..count].copy_from_slice(&self.rpl[..count]);
+            }
+            // Ranges may run in either direction, and leave I unchanged.
+            Save { first, last } => {
+                let regs = Emulator::range(*first, *last);
+                let values: Vec<u8> = regs.iter().map(|reg| self.v[*reg]).collect();
+                self.memory(self.i, values.len())?.copy_from_slice(&values);
+            }
+            Load { first, last } => {
+                let regs = Emulator::range(*first, *last);
+                let values = self.memory(self.i, regs.len())?.to_vec();
+                for (reg, val) in regs.into_iter().zip(values) {
+                    self.v[reg] = val;
+                }
+            }
+            LdLong { addr } => self.i = addr.to_resolved()?,
+            Plane { mask } => self.plane = mask.to_resolved()? as u8 & 0b11,
+            Audio => {
+                let pattern = self.memory(self.i, 16)?.to_vec();
+                self.pattern.copy_from_slice(&pattern);
+            }
+            LdPitch { reg } => self.pitch = self.v(*reg),
+        }
+        Ok(())
+    }
+
+    /// Renders the framebuffer as one line of text per row, with `.` for
+    /// unlit pixels and `#` for pixels lit in the first bitplane. Pixels lit
+    /// in the second plane are `+`, or `@` if lit in both.
+    pub fn dump_screen(&self) -> String {
+        let (width, height) = self.size();
+        let mut out = String::with_capacity((width + 1) * height);
+        for y in 0..height {
+            for x in 0..width {
+                let lit = |plane: usize| self.screen[plane][y] << x >> (HIRES_WIDTH - 1) == 1;
+                out.push(match (lit(0), lit(1)) {
+                    (false, false) => '.',
+                    (true, false) => '#',
+                    (false, true) => '+',
+                    (true, true) => '@',
+                });
+            }
+            writeln!(out).unwrap();
+        }
+        out
+    }
+
+    fn draw(&mut self, x: Register, y: Register, height: u16) -> Result<()> {
+        let (width, screen_height) = self.size();
+        // A height of 0 draws a 16x16 sprite, stored as two bytes per row.
+        let (cols, rows) = match height {
+            0 => (16, 16),
+            n => (8, usize::from(n)),
+        };
+        // The starting position always wraps; the rest of the sprite is
+        // clipped at the edges unless `wrap_sprites` is set.
+        let x = usize::from(self.v(x)) % width;
+        let y = usize::from(self.v(y)) % screen_height;
+        let wrap = self.quirks.wrap_sprites;
+        let rows = match wrap {
+            true => rows,
+            false => rows.min(screen_height - y),
+        };
+        // Each selected plane takes the next sprite's worth of bytes.
+        let len = rows * cols / 8;
+        let selected = self.plane.count_ones() as usize;
+        let sprite = self.memory(self.i, len * selected)?.to_vec();
+        let mask = self.row_mask();
+        let mut collision = false;
+        for (screen, sprite) in self.planes().zip(sprite.chunks(len)) {
+            for (i, bytes) in sprite.chunks(cols / 8).enumerate() {
+                let bits = bytes.iter().fold(0, |acc, b| acc << 8 | u128::from(*b));
+                let wide = bits << (HIRES_WIDTH - cols);
+                let mut pixels = wide >> x;
+                if wrap {
+                    pixels |= wide.checked_shl((width - x) as u32).unwrap_or(0);
+                }
+                pixels &= mask;
+                let row = &mut screen[(y + i) % screen_height];
+                collision |= *row & pixels != 0;
+                *row ^= pixels;
+            }
+        }
+        self.set(Register::VF, collision.into());
+        self.drawn = true;
+        Ok(())
+    }
+
+    fn size(&self) -> (usize, usize) {
+        match self.hires {
+            true => (HIRES_WIDTH, HIRES_HEIGHT),
+            false => (WIDTH, HEIGHT),
+        }
+    }
+
+    /// The bitplanes selected by `PLANE n`.
+    fn planes(&mut self) -> impl Iterator<Item = &mut [u128; HIRES_HEIGHT]> {
+        let plane = self.plane;
+        self.screen
+            .iter_mut()
+            .enumerate()
+            .filter(move |(i, _)| plane >> i & 1 == 1)
+            .map(|(_, screen)| screen)
+    }
+
+    /// The registers from `first` to `last` inclusive, in that order.
+    fn range(first: Register, last: Register) -> Vec<usize> {
+        let (first, last) = (first as usize, last as usize);
+        match first <= last {
+            true => (first..=last).collect(),
+            false => (last..=first).rev().collect(),
+        }
+    }
+
+    /// The bits of a screen row that are visible at the current resolution.
+    fn row_mask(&self) -> u128 {
+        u128::MAX << (HIRES_WIDTH - self.size().0)
+    }
+
+    fn memory(&mut self, addr: u16, len: usize) -> Result<&mut [u8]> {
+        let start = usize::from(addr);
+        let pc = self.pc.wrapping_sub(2);
+        self.memory
+            .get_mut(start..start + len)
+            .ok_or(Error::MemoryAccess {
+                pc,
+                addr: (start + len - 1) as u32,
+            })
+    }
+
+    fn v(&self, reg: Register) -> u8 {
+        self.v[reg as usize]
+    }
+
+    fn set(&mut self, reg: Register, val: u8) {
+        self.v[reg as usize] = val;
+    }
+
+    fn set_with_flag(&mut self, reg: Register, val: u8, flag: bool) {
+        self.set(reg, val);
+        self.set(Register::VF, flag.into());
+    }
+
+    fn logic(&mut self, dest: Register, val: u8) {
+        self.set(dest, val);
+        if self.quirks.logic_resets_vf {
+            self.set(Register::VF, 0);
+        }
+    }
+
+    fn advance_i(&mut self, count: usize) {
+        if self.quirks.load_store_increments_i {
+            self.i = self.i.wrapping_add(count as u16);
+        }
+    }
+
+    /// The memory from `addr` to the end, or nothing if `addr` is past it.
+    fn code_at(&self, addr: u16) -> &[u8] {
+        self.memory.get(usize::from(addr)..).unwrap_or_default()
+    }
+
+    /// Skips the next instruction, which is four bytes for `LD I, LONG`.
+    fn skip_if(&mut self, cond: bool) {
+        if cond {
+            let size = Instruction::from_bytes(self.code_at(self.pc)).map_or(2, |inst| inst.size());
+            self.pc = self.pc.wrapping_add(size);
+        }
+    }
+
+    fn key_down(&self, reg: Register) -> bool {
+        self.keys & (1 << (self.v(reg) & 0xF)) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assert_ok, quirks::Platform, test_utils::assemble_for};
+
+    fn run(text: &str, cycles: u64, keypad: &mut impl Keypad) -> Emulator {
+        run_on(text, cycles, keypad, Platform::Modern)
+    }
+
+    fn run_on(text: &str, cycles: u64, keypad: &mut impl Keypad, target: Platform) -> Emulator {
+        let rom = assemble_for(text, target);
+        let mut emu = assert_ok!(Emulator::new(&rom, 0x200, target, target.memory_size()));
+        for _ in 0..cycles {
+            assert_ok!(emu.step(keypad));
+        }
+        emu
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let text = "
+            LD V0, 200
+            LD V1, 100
+            ADD V0, V1
+            LD V2, VF
+            LD V3, 5
+            SUB V3, V1
+            LD V4, VF
+            LD V5, 0x81
+            SHL V5
+            LD V6, VF
+            LD I, 0x300
+            LD B, V1
+        ";
+        let emu = run(text, 12, &mut Script::default());
+        assert_eq!(emu.v[..=6], [44, 100, 1, 161, 0, 0x02, 1]);
+        assert_eq!(emu.memory[0x300..0x303], [1, 0, 0]);
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let text = "
+                CALL sub
+                SE V0, 1
+                JP fail
+                LD V1, 0xAA
+            end:
+                JP end
+            sub:
+                LD V0, 1
+                RET
+            fail:
+                LD V1, 0xFF
+        ";
+        let emu = run(text, 20, &mut Script::default());
+        assert_eq!(emu.v[1], 0xAA);
+        assert_eq!(emu.pc, 0x208);
+        assert!(emu.stack.is_empty());
+        let mut emu = assert_ok!(Emulator::new(
+            &[0x00, 0xEE],
+            0x200,
+            Platform::Modern,
+            0x1000
+        ));
+        assert!(matches!(
+            emu.step(&mut Script::default()),
+            Err(Error::StackUnderflow(0x200))
+        ));
+    }
+
+    #[test]
+    fn test_memory_size() {
+        // Reading past the end of a 2K memory faults.
+        let rom = assemble_for("LD I, 0x900\nLD V0, I\n", Platform::Modern);
+        let mut emu = assert_ok!(Emulator::new(&rom, 0x200, Platform::Modern, 0x800));
+        assert_ok!(emu.step(&mut Script::default()));
+        assert!(matches!(
+            emu.step(&mut Script::default()),
+            Err(Error::MemoryAccess {
+                pc: 0x202,
+                addr: 0x900
+            })
+        ));
+        assert!(matches!(
+            Emulator::new(&rom, 0x800, Platform::Modern, 0x800),
+            Err(Error::Overflow { .. })
+        ));
+    }
+
+    #[test]
+    fn test_draw() {
+        let text = "
+            LD V0, 0xA
+            LD F, V0
+            LD V1, 62
+            LD V2, 30
+            DRW V1, V2, 5
+            DRW V1, V2, 5
+        ";
+        let emu = run(text, 5, &mut Script::default());
+        let screen = emu.dump_screen();
+        let rows: Vec<&str> = screen.lines().collect();
+        assert_eq!(rows.len(), HEIGHT);
+        assert_eq!(&rows[30][60..], "..##");
+        assert_eq!(&rows[31][60..], "..#.");
+        assert_eq!(emu.v[0xF], 0);
+        let emu = run(text, 6, &mut Script::default());
+        assert!(!emu.dump_screen().contains('#'));
+        assert_eq!(emu.v[0xF], 1);
+    }
+
+    #[test]
+    fn test_keys_and_timers() {
+        let text = "
+            LD V0, K
+            LD V1, 20
+            LD DT, V1
+        wait:
+            LD V2, DT
+            SE V2, 0
+            JP wait
+            SKP V0
+            LD V3, 1
+        ";
+        let mut keypad = Script::new(vec![(5, 0b1000_0000), (8, 0)]);
+        let emu = run(text, 300, &mut keypad);
+        assert_eq!(emu.v[0], 7);
+        assert_eq!(emu.v[3], 1);
+        assert_eq!(emu.dt, 0);
+    }
+
+    #[test]
+    fn test_quirks() {
+        let text = "
+            LD V1, 0x81
+            LD V2, 0x03
+            SHR V1, V2
+            LD VF, 1
+            OR V2, V2
+            LD I, 0x300
+            LD I, V1
+            LD V3, 0x30
+            JP V0, 0x300 + 0x10
+        ";
+        let modern = run_on(text, 9, &mut Script::default(), Platform::Modern);
+        assert_eq!(modern.v[1], 0x40);
+        assert_eq!((modern.v[0xF], modern.i, modern.pc), (1, 0x300, 0x310));
+        let vip = run_on(text, 9, &mut Script::default(), Platform::Vip);
+        assert_eq!(vip.v[1], 0x01);
+        assert_eq!((vip.v[0xF], vip.i, vip.pc), (0, 0x302, 0x310));
+        let schip = run_on(text, 9, &mut Script::default(), Platform::Schip);
+        assert_eq!(schip.pc, 0x340);
+
+        let text = "
+            LD V0, 62
+            LD F, V0
+            DRW V0, V0, 5
+            DRW V0, V0, 5
+        ";
+        let xo = run_on(text, 3, &mut Script::default(), Platform::XoChip);
+        let rows: Vec<String> = xo.dump_screen().lines().map(String::from).collect();
+        assert_eq!(&rows[30][..2], "##");
+        assert_eq!(&rows[31][62..], "#.");
+        assert_eq!(&rows[2][62..], "##");
+        let vip = run_on(text, 10, &mut Script::default(), Platform::Vip);
+        assert!(vip.dump_screen().contains('#'));
+        assert_eq!(vip.pc, 0x206);
+    }
+
+    #[test]
+    fn test_schip() {
+        let text = "
+            HIGH
+            LD V0, 8
+            LD HF, V0
+            LD V1, 120
+            LD V2, 60
+            DRW V1, V2, 0
+            SCL
+            SCD 2
+            LD R, V2
+            LD V2, 0
+            LD V2, R
+            EXIT
+            LD V3, 1
+        ";
+        let emu = run_on(text, 20, &mut Script::default(), Platform::Schip);
+        let screen = emu.dump_screen();
+        let rows: Vec<&str> = screen.lines().collect();
+        assert_eq!((rows.len(), rows[0].len()), (HIRES_HEIGHT, HIRES_WIDTH));
+        // The top of an 8, clipped at the bottom right corner, then scrolled
+        // 4 left and 2 down.
+        assert_eq!(&rows[62][116..], "..####......");
+        assert_eq!(&rows[63][116..], "##....##....");
+        assert_eq!((emu.v[2], emu.v[3], emu.pc), (60, 0, 0x218));
+    }
+
+    #[test]
+    fn test_xochip() {
+        let text = "
+            LD V1, 1
+            LD V2, 2
+            SE V0, 0
+            LD I, LONG 0
+            LD I, LONG sprite
+            SAVE V2 - V1
+            LOAD V1 - V2
+            PLANE 3
+            LD V0, 0
+            DRW V0, V0, 2
+            SCU 1
+            LD PITCH, V1
+        end:
+            JP end
+            .org 0x2000
+        sprite:
+            db 0x00, 0x00, 0x40, 0xC0
+        ";
+        let emu = run_on(text, 12, &mut Script::default(), Platform::XoChip);
+        assert_eq!(emu.memory.len(), 0x10000);
+        assert_eq!(emu.i, 0x2000);
+        assert_eq!(emu.memory[0x2000..0x2002], [2, 1]);
+        assert_eq!(emu.v[1..3], [2, 1]);
+        assert_eq!(emu.pitch, 2);
+        // SAVE overwrote the first plane's rows with 0x02 and 0x01, and the
+        // first row of each plane scrolled off the top.
+        let screen = emu.dump_screen();
+        assert_eq!(&screen[..8], "++.....#");
+    }
+}
@@ -1,6 +1,10 @@
 use std::num::ParseIntError;
 
-use crate::parser::Rule;
+use crate::{instruction::Extension, parser::Rule, quirks::Platform};
+use pest::{
+    error::{ErrorVariant, LineColLocation},
+    Span,
+};
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -8,17 +12,267 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Parse error: {0}")]
-    Parse(#[from] pest::error::Error<Rule>),
+    Parse(Box<pest::error::Error<Rule>>),
     #[error("Invalid argument '{0}': Argument value cannot exceed {1}")]
-    ExceedBounds(u16, u16),
+    ExceedBounds(i64, u16),
     #[error("Encountered internal error: {0}")]
     Internal(String),
     #[error("Parse error: {0}")]
     NumParse(#[from] ParseIntError),
     #[error("Label '{0}' is defined twice")]
     DuplicateLabel(String),
+    #[error("Constant '{0}' is defined twice")]
+    DuplicateConstant(String),
     #[error("Io Error: {0}")]
     Io(#[from] std::io::Error),
     #[error("Label '{0}' is not defined")]
     UnresolvedLabel(String),
+    #[error("Invalid expression: {0}")]
+    Eval(String),
+    #[error("Constant '{0}' is defined in terms of itself")]
+    RecursiveConstant(String),
+    #[error("Cannot move the location counter backwards from {from:#X} to {to:#X}")]
+    OrgBackwards { from: u32, to: u16 },
+    #[error("'.org {org:#X}' at the start of the program disagrees with the origin {origin:#X}")]
+    OrgConflict { origin: u16, org: u16 },
+    #[error("'{item}' at {addr:#X} does not fit in {memory_size:#X} bytes of memory")]
+    Overflow {
+        item: String,
+        addr: u32,
+        memory_size: u32,
+    },
+    #[error("Invalid sprite row {0}: rows must be 8 pixels of '#'/'.' or '1'/'0'")]
+    SpriteRow(String),
+    #[error("'{inst}' is a {extension} instruction, which cannot be used when targeting {target}")]
+    Unsupported {
+        inst: String,
+        extension: Extension,
+        target: Platform,
+    },
+    #[error("{word:#06X} at {addr:#X} is not an instruction")]
+    InvalidInstruction { addr: u16, word: u16 },
+    #[error("CALL at {0:#X} overflows the stack")]
+    StackOverflow(u16),
+    #[error("RET at {0:#X} with an empty stack")]
+    StackUnderflow(u16),
+    #[error("Instruction at {pc:#X} accesses {addr:#X}, past the end of memory")]
+    MemoryAccess { pc: u16, addr: u32 },
+    #[error("Expected {expected}, found '{found}'")]
+    Expected { expected: String, found: String },
+    #[error("'{0}' is never closed")]
+    Unclosed(String),
+    #[error("'{0}' has no matching '{1}'")]
+    Unmatched(String, String),
+    #[error("Macro '{0}' expands without end")]
+    RecursiveMacro(String),
+    #[error("Macro '{0}' is defined twice")]
+    DuplicateMacro(String),
+    #[error("Cannot read '{path}': {error}")]
+    Read { path: String, error: std::io::Error },
+    #[error("'{0}' includes itself")]
+    IncludeCycle(String),
+    #[error("{0} only applies to chip8c assembly, not Octo sources")]
+    OctoOption(&'static str),
+    #[error("Macro '{name}' takes {expected} arguments, but {found} were given")]
+    MacroArgs {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("{}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))]
+    Multiple(Vec<Error>),
+    #[error("{}", render(.report, .notes))]
+    Located {
+        error: Box<Error>,
+        report: Box<pest::error::Error<Rule>>,
+        notes: Vec<pest::error::Error<Rule>>,
+    },
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum Warning {
+    #[error("DRW draws {} of sprite '{label}', which is {} tall", rows(*.drawn), rows(*.height))]
+    SpriteHeight {
+        label: String,
+        height: u8,
+        drawn: u8,
+    },
+    #[error("'{inst}' {behaviour} on {target}")]
+    Quirk {
+        inst: String,
+        behaviour: String,
+        target: Platform,
+    },
+    #[error("JP V0, {target:#X} at {addr:#X} is an indirect jump and cannot be followed")]
+    IndirectJump { addr: u16, target: u16 },
+    #[error("{word:#06X} at {addr:#X} is reachable but is not an instruction")]
+    InvalidInstruction { addr: u16, word: u16 },
+    #[error("{}", render(.report, .notes))]
+    Located {
+        warning: Box<Warning>,
+        report: Box<pest::error::Error<Rule>>,
+        notes: Vec<pest::error::Error<Rule>>,
+    },
+}
+
+impl Warning {
+    /// Returns the line and column the warning points at, if it has a
+    /// location.
+    pub fn line_col(&self) -> Option<(usize, usize)> {
+        match self {
+            Warning::Located { report, .. } => match report.line_col {
+                LineColLocation::Pos(pos) | LineColLocation::Span(pos, _) => Some(pos),
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the underlying warning, without its location.
+    pub fn into_kind(self) -> Warning {
+        match self {
+            Warning::Located { warning, .. } => *warning,
+            warning => warning,
+        }
+    }
+}
+
+impl From<pest::error::Error<Rule>> for Error {
+    fn from(e: pest::error::Error<Rule>) -> Self {
+        Error::Parse(Box::new(e))
+    }
+}
+
+impl Error {
+    /// Attaches the source location of `span` to the error, unless it already
+    /// has one.
+    pub fn at(self, span: Span<'_>) -> Error {
+        match self {
+            Error::Located { .. } => self,
+            error => Error::Located {
+                report: Box::new(report(&error.to_string(), span)),
+                error: Box::new(error),
+                notes: Vec::new(),
+            },
+        }
+    }
+
+    /// Adds notes that were rendered ahead of time, such as the macro calls a
+    /// statement was expanded from. A parse error gains a location so that it
+    /// can carry them.
+    pub fn with_notes(self, extra: &[pest::error::Error<Rule>]) -> Error {
+        match self {
+            _ if extra.is_empty() => self,
+            Error::Parse(report) => Error::Located {
+                error: Box::new(Error::Parse(report.clone())),
+                report,
+                notes: extra.to_vec(),
+            },
+            Error::Multiple(errors) => {
+                Error::Multiple(errors.into_iter().map(|e| e.with_notes(extra)).collect())
+            }
+            Error::Located {
+                error,
+                report,
+                mut notes,
+            } => {
+                notes.extend_from_slice(extra);
+                Error::Located {
+                    error,
+                    report,
+                    notes,
+                }
+            }
+            error => error,
+        }
+    }
+
+    /// Names the file the error's main location is in, leaving notes, which
+    /// may be in other files, alone. Then adds `extra` notes.
+    pub fn within(self, path: Option<&str>, extra: &[pest::error::Error<Rule>]) -> Error {
+        let error = match (self, path) {
+            (Error::Multiple(errors), _) => {
+                return Error::Multiple(errors.into_iter().map(|e| e.within(path, extra)).collect())
+            }
+            (Error::Parse(report), Some(path)) => Error::Parse(Box::new(report.with_path(path))),
+            (
+                Error::Located {
+                    error,
+                    report,
+                    notes,
+                },
+                Some(path),
+            ) => Error::Located {
+                error,
+                report: Box::new(report.with_path(path)),
+                notes,
+            },
+            (error, _) => error,
+        };
+        error.with_notes(extra)
+    }
+
+    /// Names the file the error's locations refer to.
+    pub fn with_path(self, path: &str) -> Error {
+        match self {
+            Error::Parse(e) => Error::Parse(Box::new(e.with_path(path))),
+            Error::Multiple(errors) => {
+                Error::Multiple(errors.into_iter().map(|e| e.with_path(path)).collect())
+            }
+            Error::Located {
+                error,
+                report,
+                notes,
+            } => Error::Located {
+                error,
+                report: Box::new(report.with_path(path)),
+                notes: notes.into_iter().map(|n| n.with_path(path)).collect(),
+            },
+            error => error,
+        }
+    }
+
+    /// Returns the line and column the error points at, if it has a location.
+    pub fn line_col(&self) -> Option<(usize, usize)> {
+        let report = match self {
+            Error::Parse(report) | Error::Located { report, .. } => report,
+            _ => return None,
+        };
+        match report.line_col {
+            LineColLocation::Pos(pos) | LineColLocation::Span(pos, _) => Some(pos),
+        }
+    }
+
+    /// Returns the underlying error, without its location.
+    pub fn into_kind(self) -> Error {
+        match self {
+            Error::Located { error, .. } => *error,
+            error => error,
+        }
+    }
+}
+
+pub(crate) fn report(message: &str, span: Span<'_>) -> pest::error::Error<Rule> {
+    pest::error::Error::new_from_span(
+        ErrorVariant::CustomError {
+            message: message.to_string(),
+        },
+        span,
+    )
+}
+
+/// A number of sprite rows, such as "1 row" or "3 rows".
+fn rows(count: u8) -> String {
+    match count {
+        1 => "1 row".to_string(),
+        _ => format!("{count} rows"),
+    }
+}
+
+fn render(report: &pest::error::Error<Rule>, notes: &[pest::error::Error<Rule>]) -> String {
+    let mut out = report.to_string();
+    for note in notes {
+        out.push('\n');
+        out.push_str(&note.to_string());
+    }
+    out
 }
@@ -0,0 +1,314 @@
+use crate::{
+    error::*,
+    parser::{parse_bounded, Rule},
+};
+use pest::{
+    iterators::Pair,
+    pratt_parser::{Assoc, Op, PrattParser},
+};
+use std::fmt;
+
+/// An operator applied to one operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    LowByte,
+    HighByte,
+}
+
+/// An operator applied to two operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Shl,
+    Shr,
+    And,
+    Or,
+    Xor,
+}
+
+/// An operand's expression, which may refer to labels and constants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Num(i64),
+    Symbol(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl TryFrom<Pair<'_, Rule>> for Expr {
+    type Error = Error;
+    fn try_from(value: Pair<'_, Rule>) -> Result<Self> {
+        match value.as_rule() {
+            Rule::addr | Rule::operand => Expr::try_from(value.into_inner().next().unwrap()),
+            Rule::expr => {
+                // Lowest precedence first, as in C.
+                let pratt = PrattParser::new()
+                    .op(Op::infix(Rule::op_or, Assoc::Left))
+                    .op(Op::infix(Rule::op_xor, Assoc::Left))
+                    .op(Op::infix(Rule::op_and, Assoc::Left))
+                    .op(Op::infix(Rule::op_shl, Assoc::Left) | Op::infix(Rule::op_shr, Assoc::Left))
+                    .op(Op::infix(Rule::op_add, Assoc::Left) | Op::infix(Rule::op_sub, Assoc::Left))
+                    .op(Op::infix(Rule::op_mul, Assoc::Left)
+                        | Op::infix(Rule::op_div, Assoc::Left)
+                        | Op::infix(Rule::op_mod, Assoc::Left));
+                pratt
+                    .map_primary(Expr::try_from)
+                    .map_infix(|lhs, op, rhs| {
+                        let op = match op.as_rule() {
+                            Rule::op_add => BinaryOp::Add,
+                            Rule::op_sub => BinaryOp::Sub,
+                            Rule::op_mul => BinaryOp::Mul,
+                            Rule::op_div => BinaryOp::Div,
+                            Rule::op_mod => BinaryOp::Mod,
+                            Rule::op_shl => BinaryOp::Shl,
+                            Rule::op_shr => BinaryOp::Shr,
+                            Rule::op_and => BinaryOp::And,
+                            Rule::op_or => BinaryOp::Or,
+                            Rule::op_xor => BinaryOp::Xor,
+                            other => {
+                                return Err(Error::Internal(format!(
+                                    "Cannot parse a binary operator from a Pair with Rule {:?}",
+                                    other
+                                )))
+                            }
+                        };
+                        Ok(Expr::Binary(op, Box::new(lhs?), Box::new(rhs?)))
+                    })
+                    .parse(value.into_inner())
+            }
+            Rule::unary => {
+                let mut inner = value.into_inner();
+                let op = match inner.next().unwrap().as_rule() {
+                    Rule::op_neg => UnaryOp::Neg,
+                    Rule::op_not => UnaryOp::Not,
+                    Rule::op_lo => UnaryOp::LowByte,
+                    Rule::op_hi => UnaryOp::HighByte,
+                    other => {
+                        return Err(Error::Internal(format!(
+                            "Cannot parse a unary operator from a Pair with Rule {:?}",
+                            other
+                        )))
+                    }
+                };
+                Ok(Expr::Unary(op, Box::new(inner.next().unwrap().try_into()?)))
+            }
+            Rule::imm => Ok(Expr::Num(parse_bounded::<u16>(value, 0xFFFF)?.into())),
+            Rule::label_inner | Rule::qualified_label | Rule::local_label | Rule::anon_ref => {
+                Ok(Expr::Symbol(value.as_str().to_string()))
+            }
+            other => Err(Error::Internal(format!(
+                "Cannot parse an Expr from a Pair with Rule {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+impl Expr {
+    pub fn is_constant(&self) -> bool {
+        match self {
+            Expr::Num(_) => true,
+            Expr::Symbol(_) => false,
+            Expr::Unary(_, e) => e.is_constant(),
+            Expr::Binary(_, l, r) => l.is_constant() && r.is_constant(),
+        }
+    }
+
+    pub fn eval(&self, lookup: &mut impl FnMut(&str) -> Result<i64>) -> Result<i64> {
+        let overflow = || Error::Eval(format!("'{self}' overflows"));
+        Ok(match self {
+            Expr::Num(n) => *n,
+            Expr::Symbol(s) => lookup(s)?,
+            Expr::Unary(op, e) => {
+                let val = e.eval(lookup)?;
+                match op {
+                    UnaryOp::Neg => val.checked_neg().ok_or_else(overflow)?,
+                    UnaryOp::Not => !val,
+                    UnaryOp::LowByte => val & 0xFF,
+                    UnaryOp::HighByte => (val >> 8) & 0xFF,
+                }
+            }
+            Expr::Binary(op, l, r) => {
+                let (l, r) = (l.eval(lookup)?, r.eval(lookup)?);
+                match op {
+                    BinaryOp::Add => l.checked_add(r).ok_or_else(overflow)?,
+                    BinaryOp::Sub => l.checked_sub(r).ok_or_else(overflow)?,
+                    BinaryOp::Mul => l.checked_mul(r).ok_or_else(overflow)?,
+                    BinaryOp::Div | BinaryOp::Mod if r == 0 => {
+                        return Err(Error::Eval(format!("'{self}' divides by zero")))
+                    }
+                    BinaryOp::Div => l.checked_div(r).ok_or_else(overflow)?,
+                    BinaryOp::Mod => l.checked_rem(r).ok_or_else(overflow)?,
+                    BinaryOp::Shl => u32::try_from(r)
+                        .ok()
+                        // checked_shl only bounds the shift amount; shifting back
+                        // catches bits (or the sign) lost off the top.
+                        .and_then(|r| l.checked_shl(r).filter(|shifted| shifted >> r == l))
+                        .ok_or_else(overflow)?,
+                    BinaryOp::Shr => u32::try_from(r)
+                        .ok()
+                        .and_then(|r| l.checked_shr(r))
+                        .ok_or_else(overflow)?,
+                    BinaryOp::And => l & r,
+                    BinaryOp::Or => l | r,
+                    BinaryOp::Xor => l ^ r,
+                }
+            }
+        })
+    }
+
+    pub fn eval_bounded(
+        &self,
+        max: u16,
+        lookup: &mut impl FnMut(&str) -> Result<i64>,
+    ) -> Result<u16> {
+        fit_bounds(self.eval(lookup)?, max)
+    }
+}
+
+/// Checks that `val` fits in an operand whose largest value is `max`. Negative
+/// values are accepted down to `-(max + 1)` and stored in two's complement.
+pub fn fit_bounds(val: i64, max: u16) -> Result<u16> {
+    let wide = i64::from(max);
+    if (0..=wide).contains(&val) {
+        Ok(val as u16)
+    } else if (-(wide + 1)..0).contains(&val) {
+        Ok((val & wide) as u16)
+    } else {
+        Err(Error::ExceedBounds(val, max))
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "~",
+            UnaryOp::LowByte => "<",
+            UnaryOp::HighByte => ">",
+        })
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::And => "&",
+            BinaryOp::Or => "|",
+            BinaryOp::Xor => "^",
+        })
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Num(n) => write!(f, "{n:#X}"),
+            Expr::Symbol(s) => f.write_str(s),
+            Expr::Unary(op, e) if matches!(**e, Expr::Binary(..)) => write!(f, "{op}({e})"),
+            Expr::Unary(op, e) => write!(f, "{op}{e}"),
+            Expr::Binary(op, l, r) => {
+                for (i, e) in [l, r].into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " {op} ")?;
+                    }
+                    match e.as_ref() {
+                        Expr::Binary(..) => write!(f, "({e})")?,
+                        e => write!(f, "{e}")?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_ok;
+    use pest::Parser as ParserTrait;
+
+    fn parse(text: &str) -> Expr {
+        let mut pairs = match <crate::parser::Parser as ParserTrait<Rule>>::parse(Rule::expr, text)
+        {
+            Ok(pairs) => pairs,
+            Err(e) => panic!("{:?}", e),
+        };
+        assert_ok!(Expr::try_from(pairs.next().unwrap()))
+    }
+
+    fn eval(text: &str) -> i64 {
+        let mut lookup = |name: &str| match name {
+            "table" => Ok(0x234),
+            "WIDTH" => Ok(64),
+            _ => Err(Error::UnresolvedLabel(name.to_string())),
+        };
+        assert_ok!(parse(text).eval(&mut lookup))
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("1 << 2 + 1"), 8);
+        assert_eq!(eval("0xF0 | 0x0F & 0x3"), 0xF3);
+        assert_eq!(eval("10 - 4 - 3"), 3);
+        assert_eq!(eval("-WIDTH + 1"), -63);
+        assert_eq!(eval("~0 & 0xFF"), 0xFF);
+    }
+
+    #[test]
+    fn test_symbols() {
+        assert_eq!(eval("table + 4"), 0x238);
+        assert_eq!(eval("(WIDTH - 1) & 0x3F"), 0x3F);
+        assert_eq!(eval("<table"), 0x34);
+        assert_eq!(eval(">table"), 0x02);
+        assert_eq!(eval(">(table + 0x100)"), 0x03);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let mut lookup = |name: &str| Err(Error::UnresolvedLabel(name.to_string()));
+        assert!(matches!(
+            parse("-1").eval_bounded(0xFF, &mut lookup),
+            Ok(0xFF)
+        ));
+        assert!(matches!(
+            parse("0x80 * 2").eval_bounded(0xFF, &mut lookup),
+            Err(Error::ExceedBounds(0x100, 0xFF))
+        ));
+        assert!(matches!(
+            parse("1 / (2 - 2)").eval_bounded(0xFF, &mut lookup),
+            Err(Error::Eval(_))
+        ));
+        // i64::MIN / -1 and i64::MIN % -1 overflow rather than panicking.
+        for op in ["/", "%"] {
+            let text = format!("-(1 << 62) * 2 {op} -1");
+            assert!(
+                matches!(parse(&text).eval(&mut lookup), Err(Error::Eval(_))),
+                "{text}"
+            );
+        }
+        for text in ["1 << 63", "3 << 62", "-1 << 64"] {
+            assert!(
+                matches!(parse(text).eval(&mut lookup), Err(Error::Eval(_))),
+                "{text}"
+            );
+        }
+        assert!(matches!(parse("-1 << 63").eval(&mut lookup), Ok(i64::MIN)));
+    }
+}
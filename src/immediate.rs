@@ -0,0 +1,69 @@
+use crate::{
+    error::*,
+    expr::{fit_bounds, Expr},
+    parser::Rule,
+};
+use pest::iterators::Pair;
+use std::fmt;
+
+/// An immediate operand, which is an expression until it is resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Immediate {
+    Expr(Expr),
+    Value(u16),
+}
+
+impl Immediate {
+    pub fn parse(value: Pair<'_, Rule>, max: u16) -> Result<Self> {
+        let span = value.as_span();
+        let expr = Expr::try_from(value).map_err(|e| e.at(span))?;
+        Immediate::from_expr(expr, max).map_err(|e| e.at(span))
+    }
+
+    /// Evaluates `expr` now if it is constant, or keeps it to be resolved once
+    /// labels are known.
+    pub fn from_expr(expr: Expr, max: u16) -> Result<Self> {
+        if expr.is_constant() {
+            Ok(Immediate::Value(expr.eval_bounded(max, &mut |_| {
+                Err(Error::Internal(
+                    "Constant expression referenced a symbol".into(),
+                ))
+            })?))
+        } else {
+            Ok(Immediate::Expr(expr))
+        }
+    }
+
+    pub fn unresolved(&self) -> Option<&Expr> {
+        match self {
+            Immediate::Expr(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    pub fn to_resolved(&self) -> Result<u16> {
+        match self {
+            Immediate::Expr(e) => Err(Error::UnresolvedLabel(e.to_string())),
+            Immediate::Value(n) => Ok(*n),
+        }
+    }
+
+    pub fn resolve(&mut self, val: i64, max: u16) -> Result<()> {
+        if self.unresolved().is_none() {
+            return Err(Error::Internal(
+                "Tried to resolve an already resolved Immediate".to_string(),
+            ));
+        }
+        *self = Immediate::Value(fit_bounds(val, max)?);
+        Ok(())
+    }
+}
+
+impl fmt::Display for Immediate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Immediate::Expr(e) => write!(f, "{e}"),
+            Immediate::Value(n) => write!(f, "{n:#X}"),
+        }
+    }
+}
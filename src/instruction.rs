@@ -1,10 +1,15 @@
 use crate::{
-    address::Address,
-    error::*,
-    parser::{parse_imm, Rule},
-    register::Register,
+    address::Address, error::*, expr::Expr, immediate::Immediate, parser::Rule, register::Register,
 };
 use pest::iterators::Pair;
+use std::fmt;
+
+/// Instruction sets that extend the original CHIP-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extension {
+    Schip,
+    XoChip,
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Instruction {
@@ -17,7 +22,7 @@ pub enum Instruction {
     },
     AddImm {
         reg: Register,
-        imm: u8,
+        imm: Immediate,
     },
     And {
         dest: Register,
@@ -30,7 +35,7 @@ pub enum Instruction {
     Drw {
         x: Register,
         y: Register,
-        nibble: u8,
+        nibble: Immediate,
     },
     JpRel {
         addr: Address,
@@ -68,7 +73,7 @@ pub enum Instruction {
     },
     LdImm {
         reg: Register,
-        imm: u8,
+        imm: Immediate,
     },
     LdRegRead {
         reg: Register,
@@ -80,7 +85,7 @@ pub enum Instruction {
     Ret,
     Rnd {
         reg: Register,
-        imm: u8,
+        imm: Immediate,
     },
     SeReg {
         reg0: Register,
@@ -88,13 +93,17 @@ pub enum Instruction {
     },
     SeImm {
         reg: Register,
-        imm: u8,
+        imm: Immediate,
     },
+    /// Shifts `src` left into `dest`, or `dest` in place depending on quirks.
+    /// `SHL Vx` is `SHL Vx, Vx`, which behaves the same either way.
     Shl {
-        reg: Register,
+        dest: Register,
+        src: Register,
     },
     Shr {
-        reg: Register,
+        dest: Register,
+        src: Register,
     },
     Sknp {
         reg: Register,
@@ -108,7 +117,7 @@ pub enum Instruction {
     },
     SneImm {
         reg: Register,
-        imm: u8,
+        imm: Immediate,
     },
     Sub {
         dest: Register,
@@ -125,6 +134,47 @@ pub enum Instruction {
         dest: Register,
         src: Register,
     },
+    // SUPER-CHIP
+    Scd {
+        nibble: Immediate,
+    },
+    Scr,
+    Scl,
+    Exit,
+    Low,
+    High,
+    LdBigSprite {
+        reg: Register,
+    },
+    LdRplDump {
+        reg: Register,
+    },
+    LdRplRead {
+        reg: Register,
+    },
+    // XO-CHIP
+    Save {
+        first: Register,
+        last: Register,
+    },
+    Load {
+        first: Register,
+        last: Register,
+    },
+    /// `LD I, LONG addr`, the only four-byte instruction.
+    LdLong {
+        addr: Immediate,
+    },
+    Plane {
+        mask: Immediate,
+    },
+    Audio,
+    LdPitch {
+        reg: Register,
+    },
+    Scu {
+        nibble: Immediate,
+    },
 }
 
 impl TryFrom<Pair<'_, Rule>> for Instruction {
@@ -146,7 +196,7 @@ impl TryFrom<Pair<'_, Rule>> for Instruction {
             }),
             add_imm => Ok(AddImm {
                 reg: inner.next().unwrap().try_into()?,
-                imm: parse_imm(inner.next().unwrap())?,
+                imm: Immediate::parse(inner.next().unwrap(), 0xFF)?,
             }),
             and => Ok(And {
                 dest: inner.next().unwrap().try_into()?,
@@ -156,18 +206,11 @@ impl TryFrom<Pair<'_, Rule>> for Instruction {
                 addr: inner.next().unwrap().try_into()?,
             }),
             cls => Ok(Cls),
-            drw => {
-                let (x, y, nibble) = (
-                    inner.next().unwrap().try_into()?,
-                    inner.next().unwrap().try_into()?,
-                    parse_imm(inner.next().unwrap())?,
-                );
-                if nibble > 0b1111 {
-                    Err(Error::ExceedBounds(nibble as u16, 0b1111))
-                } else {
-                    Ok(Drw { x, y, nibble })
-                }
-            }
+            drw => Ok(Drw {
+                x: inner.next().unwrap().try_into()?,
+                y: inner.next().unwrap().try_into()?,
+                nibble: Immediate::parse(inner.next().unwrap(), 0b1111)?,
+            }),
             jp_rel => Ok(JpRel {
                 addr: inner.next().unwrap().try_into()?,
             }),
@@ -181,7 +224,7 @@ impl TryFrom<Pair<'_, Rule>> for Instruction {
             ret => Ok(Ret),
             rnd => Ok(Rnd {
                 reg: inner.next().unwrap().try_into()?,
-                imm: parse_imm(inner.next().unwrap())?,
+                imm: Immediate::parse(inner.next().unwrap(), 0xFF)?,
             }),
             se => Ok(SeReg {
                 reg0: inner.next().unwrap().try_into()?,
@@ -189,14 +232,24 @@ impl TryFrom<Pair<'_, Rule>> for Instruction {
             }),
             se_imm => Ok(SeImm {
                 reg: inner.next().unwrap().try_into()?,
-                imm: parse_imm(inner.next().unwrap())?,
-            }),
-            shl => Ok(Shl {
-                reg: inner.next().unwrap().try_into()?,
-            }),
-            shr => Ok(Shr {
-                reg: inner.next().unwrap().try_into()?,
-            }),
+                imm: Immediate::parse(inner.next().unwrap(), 0xFF)?,
+            }),
+            shl => {
+                let dest: Register = inner.next().unwrap().try_into()?;
+                let src = match inner.next() {
+                    Some(p) => p.try_into()?,
+                    None => dest,
+                };
+                Ok(Shl { dest, src })
+            }
+            shr => {
+                let dest: Register = inner.next().unwrap().try_into()?;
+                let src = match inner.next() {
+                    Some(p) => p.try_into()?,
+                    None => dest,
+                };
+                Ok(Shr { dest, src })
+            }
             skp => Ok(Skp {
                 reg: inner.next().unwrap().try_into()?,
             }),
@@ -209,7 +262,7 @@ impl TryFrom<Pair<'_, Rule>> for Instruction {
             }),
             sne_imm => Ok(SneImm {
                 reg: inner.next().unwrap().try_into()?,
-                imm: parse_imm(inner.next().unwrap())?,
+                imm: Immediate::parse(inner.next().unwrap(), 0xFF)?,
             }),
             sub => Ok(Sub {
                 dest: inner.next().unwrap().try_into()?,
@@ -226,6 +279,29 @@ impl TryFrom<Pair<'_, Rule>> for Instruction {
                 dest: inner.next().unwrap().try_into()?,
                 src: inner.next().unwrap().try_into()?,
             }),
+            scd => Ok(Scd {
+                nibble: Immediate::parse(inner.next().unwrap(), 0b1111)?,
+            }),
+            scr => Ok(Scr),
+            scl => Ok(Scl),
+            exit => Ok(Exit),
+            low => Ok(Low),
+            high => Ok(High),
+            save => Ok(Save {
+                first: inner.next().unwrap().try_into()?,
+                last: inner.next().unwrap().try_into()?,
+            }),
+            load => Ok(Load {
+                first: inner.next().unwrap().try_into()?,
+                last: inner.next().unwrap().try_into()?,
+            }),
+            plane => Ok(Plane {
+                mask: Immediate::parse(inner.next().unwrap(), 0b1111)?,
+            }),
+            audio => Ok(Audio),
+            scu => Ok(Scu {
+                nibble: Immediate::parse(inner.next().unwrap(), 0b1111)?,
+            }),
             // LD
             ld_bcd => Ok(LdBcd {
                 reg: inner.next().unwrap().try_into()?,
@@ -254,7 +330,7 @@ impl TryFrom<Pair<'_, Rule>> for Instruction {
             }),
             ld_i_reg => Ok(LdImm {
                 reg: inner.next().unwrap().try_into()?,
-                imm: parse_imm(inner.next().unwrap())?,
+                imm: Immediate::parse(inner.next().unwrap(), 0xFF)?,
             }),
             ld_reg_dump => Ok(LdRegDump {
                 reg: inner.next().unwrap().try_into()?,
@@ -262,6 +338,21 @@ impl TryFrom<Pair<'_, Rule>> for Instruction {
             ld_reg_read => Ok(LdRegRead {
                 reg: inner.next().unwrap().try_into()?,
             }),
+            ld_big_sprite => Ok(LdBigSprite {
+                reg: inner.next().unwrap().try_into()?,
+            }),
+            ld_rpl_dump => Ok(LdRplDump {
+                reg: inner.next().unwrap().try_into()?,
+            }),
+            ld_rpl_read => Ok(LdRplRead {
+                reg: inner.next().unwrap().try_into()?,
+            }),
+            ld_i_long => Ok(LdLong {
+                addr: Immediate::parse(inner.nth(1).unwrap(), 0xFFFF)?,
+            }),
+            ld_pitch => Ok(LdPitch {
+                reg: inner.next().unwrap().try_into()?,
+            }),
             other => Err(Error::Internal(format!(
                 "Cannot parse a Pair with Rule type {:?} as an Instruction",
                 other
@@ -271,37 +362,161 @@ impl TryFrom<Pair<'_, Rule>> for Instruction {
 }
 
 impl Instruction {
-    pub fn unresolved_arg(&self) -> Option<&str> {
+    /// Decodes the big-endian instruction at the start of `code`. Returns
+    /// `None` for words that are not instructions, or that `as_bytes` could
+    /// not reproduce exactly.
+    pub fn from_bytes(code: &[u8]) -> Option<Instruction> {
+        use Instruction::*;
+        let bytes: [u8; 2] = code.get(..2)?.try_into().ok()?;
+        let word = u16::from_be_bytes(bytes);
+        let x = Register::try_from(bytes[0] & 0xF).ok()?;
+        let y = Register::try_from(bytes[1] >> 4).ok()?;
+        let addr = Address::Short(word & Address::MAX);
+        let imm = Immediate::Value(bytes[1].into());
+        Some(match (bytes[0] >> 4, bytes[1] & 0xF) {
+            (0x0, _) if word == 0x00E0 => Cls,
+            (0x0, _) if word == 0x00EE => Ret,
+            (0x0, n) if word & 0xFFF0 == 0x00C0 => Scd {
+                nibble: Immediate::Value(n.into()),
+            },
+            (0x0, n) if word & 0xFFF0 == 0x00D0 => Scu {
+                nibble: Immediate::Value(n.into()),
+            },
+            (0x0, _) if word == 0x00FB => Scr,
+            (0x0, _) if word == 0x00FC => Scl,
+            (0x0, _) if word == 0x00FD => Exit,
+            (0x0, _) if word == 0x00FE => Low,
+            (0x0, _) if word == 0x00FF => High,
+            (0x0, _) => Sys { addr },
+            (0x1, _) => JpAbs { addr },
+            (0x2, _) => Call { addr },
+            (0x3, _) => SeImm { reg: x, imm },
+            (0x4, _) => SneImm { reg: x, imm },
+            (0x5, 0x0) => SeReg { reg0: x, reg1: y },
+            (0x5, 0x2) => Save { first: x, last: y },
+            (0x5, 0x3) => Load { first: x, last: y },
+            (0x6, _) => LdImm { reg: x, imm },
+            (0x7, _) => AddImm { reg: x, imm },
+            (0x8, 0x0) => LdReg { dest: x, src: y },
+            (0x8, 0x1) => Or { dest: x, src: y },
+            (0x8, 0x2) => And { dest: x, src: y },
+            (0x8, 0x3) => Xor { dest: x, src: y },
+            (0x8, 0x4) => AddReg { dest: x, src: y },
+            (0x8, 0x5) => Sub { dest: x, src: y },
+            (0x8, 0x6) => Shr { dest: x, src: y },
+            (0x8, 0x7) => SubN { dest: x, src: y },
+            (0x8, 0xE) => Shl { dest: x, src: y },
+            (0x9, 0x0) => SneReg { reg0: x, reg1: y },
+            (0xA, _) => LdAddr { addr },
+            (0xB, _) => JpRel { addr },
+            (0xC, _) => Rnd { reg: x, imm },
+            (0xD, n) => Drw {
+                x,
+                y,
+                nibble: Immediate::Value(n.into()),
+            },
+            (0xE, _) => match bytes[1] {
+                0x9E => Skp { reg: x },
+                0xA1 => Sknp { reg: x },
+                _ => return None,
+            },
+            (0xF, _) if word == 0xF000 => LdLong {
+                addr: Immediate::Value(u16::from_be_bytes(code.get(2..4)?.try_into().ok()?)),
+            },
+            (0xF, _) if word == 0xF002 => Audio,
+            (0xF, _) if bytes[1] == 0x01 => Plane {
+                mask: Immediate::Value((bytes[0] & 0xF).into()),
+            },
+            (0xF, _) => match bytes[1] {
+                0x07 => LdReadDt { reg: x },
+                0x0A => LdKey { reg: x },
+                0x15 => LdSetDt { reg: x },
+                0x18 => LdSetSt { reg: x },
+                0x1E => AddI { reg: x },
+                0x29 => LdSprite { reg: x },
+                0x33 => LdBcd { reg: x },
+                0x55 => LdRegDump { reg: x },
+                0x65 => LdRegRead { reg: x },
+                0x30 => LdBigSprite { reg: x },
+                0x75 => LdRplDump { reg: x },
+                0x85 => LdRplRead { reg: x },
+                0x3A => LdPitch { reg: x },
+                _ => return None,
+            },
+            _ => return None,
+        })
+    }
+
+    pub fn unresolved_arg(&self) -> Option<&Expr> {
         use Instruction::*;
         match self {
             Sys { addr } | Call { addr } | JpRel { addr } | JpAbs { addr } | LdAddr { addr } => {
-                addr.label_value()
+                addr.unresolved()
             }
+            AddImm { imm, .. }
+            | LdImm { imm, .. }
+            | Rnd { imm, .. }
+            | SeImm { imm, .. }
+            | SneImm { imm, .. } => imm.unresolved(),
+            Drw { nibble, .. } | Scd { nibble } | Scu { nibble } => nibble.unresolved(),
+            LdLong { addr } => addr.unresolved(),
+            Plane { mask } => mask.unresolved(),
             _ => None,
         }
     }
 
-    pub fn resolve_arg(&mut self, val: u16) -> Result<()> {
+    pub fn resolve_arg(&mut self, val: i64) -> Result<()> {
         use Instruction::*;
         match self {
             Sys { ref mut addr }
             | Call { ref mut addr }
             | JpRel { ref mut addr }
             | JpAbs { ref mut addr }
-            | LdAddr { ref mut addr } => {
-                if addr.label_value().is_none() {
-                    return Err(Error::Internal(
-                        "Tried to resolve an already resolved Address".to_string(),
-                    ));
-                }
-                *addr = Address::Short(val);
-                Ok(())
+            | LdAddr { ref mut addr } => addr.resolve(val),
+            AddImm { ref mut imm, .. }
+            | LdImm { ref mut imm, .. }
+            | Rnd { ref mut imm, .. }
+            | SeImm { ref mut imm, .. }
+            | SneImm { ref mut imm, .. } => imm.resolve(val, 0xFF),
+            Drw { ref mut nibble, .. } | Scd { ref mut nibble } | Scu { ref mut nibble } => {
+                nibble.resolve(val, 0b1111)
             }
+            LdLong { ref mut addr } => addr.resolve(val, 0xFFFF),
+            Plane { ref mut mask } => mask.resolve(val, 0b1111),
             _ => Ok(()),
         }
     }
 
-    pub fn as_bytes(&self) -> Result<[u8; 2]> {
+    /// Size of the encoded instruction in bytes.
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LdLong { .. } => 4,
+            _ => 2,
+        }
+    }
+
+    /// Size in bytes of the instructions a form in the grammar parses to, for
+    /// placing one that failed to parse.
+    pub(crate) fn form_size(form: Rule) -> u16 {
+        match form {
+            Rule::ld_i_long => 4,
+            _ => 2,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Result<Vec<u8>> {
+        use Instruction::*;
+        let word = match self {
+            LdLong { addr } => {
+                let [hi, lo] = addr.to_resolved()?.to_be_bytes();
+                return Ok(vec![0xF0, 0x00, hi, lo]);
+            }
+            _ => self.word()?,
+        };
+        Ok(word.to_vec())
+    }
+
+    fn word(&self) -> Result<[u8; 2]> {
         use Instruction::*;
         Ok(match self {
             Sys { addr } => addr.to_resolved()?.to_be_bytes(),
@@ -309,25 +524,28 @@ impl Instruction {
             Ret => [0, 0xEE],
             JpAbs { addr } => (0x1000 | addr.to_resolved()?).to_be_bytes(),
             Call { addr } => (0x2000 | addr.to_resolved()?).to_be_bytes(),
-            SeImm { reg, imm } => [0x30 | *reg as u8, *imm],
-            SneImm { reg, imm } => [0x40 | *reg as u8, *imm],
+            SeImm { reg, imm } => [0x30 | *reg as u8, imm.to_resolved()? as u8],
+            SneImm { reg, imm } => [0x40 | *reg as u8, imm.to_resolved()? as u8],
             SeReg { reg0, reg1 } => [0x50 | *reg0 as u8, (*reg1 as u8) << 4],
-            LdImm { reg, imm } => [0x60 | *reg as u8, *imm],
-            AddImm { reg, imm } => [0x70 | *reg as u8, *imm],
+            LdImm { reg, imm } => [0x60 | *reg as u8, imm.to_resolved()? as u8],
+            AddImm { reg, imm } => [0x70 | *reg as u8, imm.to_resolved()? as u8],
             LdReg { dest, src } => [0x80 | *dest as u8, (*src as u8) << 4],
             Or { dest, src } => [0x80 | *dest as u8, ((*src as u8) << 4) + 1],
             And { dest, src } => [0x80 | *dest as u8, ((*src as u8) << 4) + 2],
             Xor { dest, src } => [0x80 | *dest as u8, ((*src as u8) << 4) + 3],
             AddReg { dest, src } => [0x80 | *dest as u8, ((*src as u8) << 4) + 4],
             Sub { dest, src } => [0x80 | *dest as u8, ((*src as u8) << 4) + 5],
-            Shr { reg } => [0x80 | *reg as u8, 0x06],
+            Shr { dest, src } => [0x80 | *dest as u8, ((*src as u8) << 4) + 6],
             SubN { dest, src } => [0x80 | *dest as u8, ((*src as u8) << 4) + 7],
-            Shl { reg } => [0x80 | *reg as u8, 0x0E],
+            Shl { dest, src } => [0x80 | *dest as u8, ((*src as u8) << 4) + 0xE],
             SneReg { reg0, reg1 } => [0x90 | *reg0 as u8, (*reg1 as u8) << 4],
             LdAddr { addr } => (0xA000 | addr.to_resolved()?).to_be_bytes(),
             JpRel { addr } => (0xB000 | addr.to_resolved()?).to_be_bytes(),
-            Rnd { reg, imm } => [0xC0 | *reg as u8, *imm],
-            Drw { x, y, nibble } => [0xD0 | *x as u8, ((*y as u8) << 4) | nibble],
+            Rnd { reg, imm } => [0xC0 | *reg as u8, imm.to_resolved()? as u8],
+            Drw { x, y, nibble } => [
+                0xD0 | *x as u8,
+                ((*y as u8) << 4) | nibble.to_resolved()? as u8,
+            ],
             Skp { reg } => [0xE0 | *reg as u8, 0x9E],
             Sknp { reg } => [0xE0 | *reg as u8, 0xA1],
             LdReadDt { reg } => [0xF0 | *reg as u8, 0x07],
@@ -339,6 +557,125 @@ impl Instruction {
             LdBcd { reg } => [0xF0 | *reg as u8, 0x33],
             LdRegDump { reg } => [0xF0 | *reg as u8, 0x55],
             LdRegRead { reg } => [0xF0 | *reg as u8, 0x65],
+            Scd { nibble } => [0x00, 0xC0 | nibble.to_resolved()? as u8],
+            Scr => [0x00, 0xFB],
+            Scl => [0x00, 0xFC],
+            Exit => [0x00, 0xFD],
+            Low => [0x00, 0xFE],
+            High => [0x00, 0xFF],
+            LdBigSprite { reg } => [0xF0 | *reg as u8, 0x30],
+            LdRplDump { reg } => [0xF0 | *reg as u8, 0x75],
+            LdRplRead { reg } => [0xF0 | *reg as u8, 0x85],
+            Save { first, last } => [0x50 | *first as u8, ((*last as u8) << 4) + 2],
+            Load { first, last } => [0x50 | *first as u8, ((*last as u8) << 4) + 3],
+            Plane { mask } => [0xF0 | mask.to_resolved()? as u8, 0x01],
+            Audio => [0xF0, 0x02],
+            LdPitch { reg } => [0xF0 | *reg as u8, 0x3A],
+            Scu { nibble } => [0x00, 0xD0 | nibble.to_resolved()? as u8],
+            LdLong { .. } => {
+                return Err(Error::Internal(
+                    "LD I, LONG does not fit in one word".into(),
+                ))
+            }
         })
     }
+
+    /// The instruction set this instruction belongs to, if it is not part of
+    /// the original CHIP-8. Only meaningful once arguments are resolved.
+    pub fn extension(&self) -> Option<Extension> {
+        use Instruction::*;
+        match self {
+            Scd { .. }
+            | Scr
+            | Scl
+            | Exit
+            | Low
+            | High
+            | LdBigSprite { .. }
+            | LdRplDump { .. }
+            | LdRplRead { .. } => Some(Extension::Schip),
+            Drw {
+                nibble: Immediate::Value(0),
+                ..
+            } => Some(Extension::Schip),
+            Save { .. }
+            | Load { .. }
+            | LdLong { .. }
+            | Plane { .. }
+            | Audio
+            | LdPitch { .. }
+            | Scu { .. } => Some(Extension::XoChip),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Extension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Extension::Schip => "SUPER-CHIP",
+            Extension::XoChip => "XO-CHIP",
+        })
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+        match self {
+            AddI { reg } => write!(f, "ADD I, {reg}"),
+            AddReg { dest, src } => write!(f, "ADD {dest}, {src}"),
+            AddImm { reg, imm } => write!(f, "ADD {reg}, {imm}"),
+            And { dest, src } => write!(f, "AND {dest}, {src}"),
+            Call { addr } => write!(f, "CALL {addr}"),
+            Cls => write!(f, "CLS"),
+            Drw { x, y, nibble } => write!(f, "DRW {x}, {y}, {nibble}"),
+            JpRel { addr } => write!(f, "JP V0, {addr}"),
+            JpAbs { addr } => write!(f, "JP {addr}"),
+            LdBcd { reg } => write!(f, "LD B, {reg}"),
+            LdSetDt { reg } => write!(f, "LD DT, {reg}"),
+            LdSetSt { reg } => write!(f, "LD ST, {reg}"),
+            LdSprite { reg } => write!(f, "LD F, {reg}"),
+            LdAddr { addr } => write!(f, "LD I, {addr}"),
+            LdReadDt { reg } => write!(f, "LD {reg}, DT"),
+            LdKey { reg } => write!(f, "LD {reg}, K"),
+            LdReg { dest, src } => write!(f, "LD {dest}, {src}"),
+            LdRegDump { reg } => write!(f, "LD I, {reg}"),
+            LdImm { reg, imm } => write!(f, "LD {reg}, {imm}"),
+            LdRegRead { reg } => write!(f, "LD {reg}, I"),
+            Or { dest, src } => write!(f, "OR {dest}, {src}"),
+            Ret => write!(f, "RET"),
+            Rnd { reg, imm } => write!(f, "RND {reg}, {imm}"),
+            SeReg { reg0, reg1 } => write!(f, "SE {reg0}, {reg1}"),
+            SeImm { reg, imm } => write!(f, "SE {reg}, {imm}"),
+            Shl { dest, src } if dest == src => write!(f, "SHL {dest}"),
+            Shl { dest, src } => write!(f, "SHL {dest}, {src}"),
+            Shr { dest, src } if dest == src => write!(f, "SHR {dest}"),
+            Shr { dest, src } => write!(f, "SHR {dest}, {src}"),
+            Sknp { reg } => write!(f, "SKNP {reg}"),
+            Skp { reg } => write!(f, "SKP {reg}"),
+            SneReg { reg0, reg1 } => write!(f, "SNE {reg0}, {reg1}"),
+            SneImm { reg, imm } => write!(f, "SNE {reg}, {imm}"),
+            Sub { dest, src } => write!(f, "SUB {dest}, {src}"),
+            SubN { dest, src } => write!(f, "SUBN {dest}, {src}"),
+            Sys { addr } => write!(f, "SYS {addr}"),
+            Xor { dest, src } => write!(f, "XOR {dest}, {src}"),
+            Scd { nibble } => write!(f, "SCD {nibble}"),
+            Scr => write!(f, "SCR"),
+            Scl => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Low => write!(f, "LOW"),
+            High => write!(f, "HIGH"),
+            LdBigSprite { reg } => write!(f, "LD HF, {reg}"),
+            LdRplDump { reg } => write!(f, "LD R, {reg}"),
+            LdRplRead { reg } => write!(f, "LD {reg}, R"),
+            Save { first, last } => write!(f, "SAVE {first} - {last}"),
+            Load { first, last } => write!(f, "LOAD {first} - {last}"),
+            LdLong { addr } => write!(f, "LD I, LONG {addr}"),
+            Plane { mask } => write!(f, "PLANE {mask}"),
+            Audio => write!(f, "AUDIO"),
+            LdPitch { reg } => write!(f, "LD PITCH, {reg}"),
+            Scu { nibble } => write!(f, "SCU {nibble}"),
+        }
+    }
 }
@@ -0,0 +1,206 @@
+//! Assembles CHIP-8, SUPER-CHIP and XO-CHIP programs.
+//!
+//! ```
+//! let output = chip8c::assemble("start:\n    CLS\n    JP start\n", chip8c::Options::default())?;
+//! assert_eq!(output.bytes, [0x00, 0xE0, 0x12, 0x00]);
+//! assert_eq!(output.symbols, [(0x200, "start".to_string())]);
+//! # Ok::<(), chip8c::Error>(())
+//! ```
+//!
+//! Errors and instructions are made of types exported here:
+//!
+//! ```
+//! use chip8c::{Address, Error, Expr, Extension, Instruction, Options, Platform, Rule};
+//! use pest::error::ErrorVariant;
+//!
+//! let options = Options {
+//!     target: Platform::Vip,
+//!     ..Options::default()
+//! };
+//! match chip8c::assemble("SCR\n", options).map_err(Error::into_kind) {
+//!     Err(Error::Unsupported { extension, .. }) => assert_eq!(extension, Extension::Schip),
+//!     other => panic!("{other:?}"),
+//! }
+//! match chip8c::assemble("JP\n", Options::default()) {
+//!     Err(Error::Parse(report)) => match report.variant {
+//!         ErrorVariant::ParsingError { positives, .. } => assert!(positives.contains(&Rule::expr)),
+//!         variant => panic!("{variant:?}"),
+//!     },
+//!     other => panic!("{other:?}"),
+//! }
+//!
+//! let mut jump = Instruction::JpAbs {
+//!     addr: Address::Expr(Expr::Symbol("start".into())),
+//! };
+//! jump.resolve_arg(0x200)?;
+//! assert_eq!(Instruction::from_bytes(&[0x12, 0x00]), Some(jump));
+//! # Ok::<(), chip8c::Error>(())
+//! ```
+mod address;
+mod args;
+mod assembler;
+#[doc(hidden)]
+pub mod cli;
+mod disassembler;
+mod emulator;
+mod error;
+mod expr;
+mod immediate;
+mod instruction;
+mod octo;
+mod output;
+mod parser;
+mod quirks;
+mod register;
+mod source;
+mod symbols;
+pub use crate::{
+    address::Address,
+    assembler::Options,
+    error::{Error, Result, Warning},
+    expr::{BinaryOp, Expr, UnaryOp},
+    immediate::Immediate,
+    instruction::{Extension, Instruction},
+    parser::Rule,
+    quirks::{Platform, Quirks},
+    register::Register,
+};
+use crate::{assembler::Assembler, source::Source};
+
+/// An assembled program.
+#[derive(Debug, Clone)]
+pub struct Output {
+    /// The ROM, starting at the address it is loaded at.
+    pub bytes: Vec<u8>,
+    /// Every label with its address, sorted by address and then by name.
+    /// Local labels have their full names, such as `main.loop`.
+    pub symbols: Vec<(u16, String)>,
+    /// Warnings about instructions that assembled but are probably wrong.
+    pub diagnostics: Vec<Warning>,
+}
+
+/// Assembles a program written in chip8c assembly. Files it includes are
+/// found relative to the working directory.
+pub fn assemble(text: &str, options: Options) -> Result<Output> {
+    let source = Source::expand(text, &options.defines)?;
+    let asm = Assembler::build_source(&source, &options)?;
+    let mut bytes = Vec::new();
+    asm.write_bin(&mut bytes)?;
+    Ok(Output {
+        bytes,
+        symbols: asm
+            .symbols()
+            .into_iter()
+            .map(|(addr, name)| (addr, name.to_string()))
+            .collect(),
+        diagnostics: asm.warnings().to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod test_macros {
+    #[macro_export]
+    macro_rules! assert_ok {
+        ($val:ident) => {{
+            assert!($val.is_ok(), "{:?}", $val.unwrap_err());
+            $val.unwrap()
+        }};
+        ($e:expr) => {{
+            let val = $e;
+            assert_ok!(val)
+        }};
+    }
+}
+
+/// Builds the programs that tests of other modules start from.
+#[cfg(test)]
+mod test_utils {
+    use crate::{assembler::Assembler, assert_ok, parser::Parser, Options, Platform};
+
+    /// The options for assembling for `target`, with its memory size.
+    pub fn options_for(target: Platform) -> Options {
+        Options {
+            memory_size: target.memory_size(),
+            target,
+            ..Options::default()
+        }
+    }
+
+    /// Assembles `text` for `target`, failing the test if it does not
+    /// assemble.
+    pub fn assemble_for(text: &str, target: Platform) -> Vec<u8> {
+        let parsed = match Parser::parse(text) {
+            Ok(iter) => iter,
+            Err(e) => panic!("{e}\n{text}"),
+        };
+        let asm = assert_ok!(Assembler::build(parsed, &options_for(target)));
+        let mut dest = Vec::new();
+        assert_ok!(asm.write_bin(&mut dest));
+        dest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::Assembler, assert_ok, parser::Parser};
+
+    #[test]
+    fn test_assemble() {
+        let mut dest: Vec<u8> = Default::default();
+        let progs = [
+            include_str!("../test_files/instructions.asm"),
+            include_str!("../test_files/labels.asm"),
+            include_str!("../test_files/comments.asm"),
+        ];
+        for text in progs {
+            let parsed = match Parser::parse(text) {
+                Ok(iter) => iter,
+                Err(e) => panic!("{:?}", e),
+            };
+            let asm = assert_ok!(Assembler::build(parsed, &Options::default()));
+            assert_ok!(asm.write_bin(&mut dest));
+            dest.clear();
+        }
+    }
+
+    #[test]
+    fn test_parse_errors_recover() {
+        let text = "CLS\nLD V0,\nRET\nBOGUS V1\nCLS\n";
+        let errors = match Parser::parse(text) {
+            Err(Error::Multiple(errors)) => errors,
+            Err(other) => panic!("{other}"),
+            Ok(_) => panic!("parsed invalid text"),
+        };
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].to_string().contains("2:"), "{}", errors[0]);
+        assert!(errors[1].to_string().contains("4:1"), "{}", errors[1]);
+    }
+
+    #[test]
+    fn test_library() {
+        let text = "macro twice reg\n    ADD reg, 1\n    ADD reg, 1\nendm\n\
+                    main:\n    twice V1\n.loop:\n    SHR V0, V1\n    JP .loop\n";
+        let options = Options {
+            target: Platform::Chip48,
+            ..Options::default()
+        };
+        let output = assert_ok!(assemble(text, options));
+        assert_eq!(
+            output.bytes,
+            [0x71, 0x01, 0x71, 0x01, 0x80, 0x16, 0x12, 0x04]
+        );
+        assert_eq!(
+            output.symbols,
+            [
+                (0x200, "main".to_string()),
+                (0x204, "main.loop".to_string())
+            ]
+        );
+        assert_eq!(output.diagnostics.len(), 1);
+        assert!(matches!(
+            assemble("JP nowhere", Options::default()),
+            Err(Error::Located { .. })
+        ));
+    }
+}
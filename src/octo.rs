@@ -0,0 +1,989 @@
+use crate::{
+    address::Address,
+    assembler::{InstSpans, Item},
+    error::*,
+    expr::{BinaryOp, Expr, UnaryOp},
+    immediate::Immediate,
+    instruction::Instruction,
+    register::Register,
+};
+use pest::{Parser as ParserTrait, Span};
+use pest_derive::Parser as ParserDerive;
+use std::collections::{HashMap, VecDeque};
+
+#[derive(ParserDerive)]
+#[grammar = "octo.pest"]
+struct OctoParser;
+
+/// Words with a fixed meaning, which cannot name labels, constants or macros.
+const KEYWORDS: [&str; 37] = [
+    "return",
+    "clear",
+    "bcd",
+    "save",
+    "load",
+    "saveflags",
+    "loadflags",
+    "sprite",
+    "jump",
+    "jump0",
+    "native",
+    "hires",
+    "lores",
+    "exit",
+    "scroll-down",
+    "scroll-up",
+    "scroll-left",
+    "scroll-right",
+    "audio",
+    "plane",
+    "i",
+    "delay",
+    "buzzer",
+    "pitch",
+    "if",
+    "then",
+    "begin",
+    "else",
+    "end",
+    "loop",
+    "again",
+    "while",
+    "key",
+    "random",
+    "hex",
+    "bighex",
+    "long",
+];
+
+/// Macro expansions allowed in one program, so that a macro which expands
+/// itself is reported instead of expanded forever.
+const MAX_EXPANSIONS: usize = 100_000;
+
+#[derive(Debug, Clone)]
+struct Token<'a> {
+    rule: Rule,
+    span: Span<'a>,
+}
+
+impl<'a> Token<'a> {
+    fn text(&self) -> &'a str {
+        self.span.as_str()
+    }
+}
+
+#[derive(Debug)]
+struct Macro<'a> {
+    params: Vec<&'a str>,
+    body: Vec<Token<'a>>,
+}
+
+/// An `if ... begin` or `loop` that has not been closed yet. Each is numbered
+/// so that the labels its jumps target are unique. The labels are named with
+/// a `#`, which marks them as synthesized.
+#[derive(Debug)]
+enum Block<'a> {
+    If {
+        id: usize,
+        has_else: bool,
+        start: Span<'a>,
+    },
+    Loop {
+        id: usize,
+        start: Span<'a>,
+    },
+}
+
+enum Operand<'a> {
+    Register(Register),
+    Value(Expr, Span<'a>),
+}
+
+/// Lowers Octo source to statements for `Assembler::build_items`.
+pub fn lower(text: &str) -> Result<Vec<(Item<'_>, Span<'_>)>> {
+    let prog = <OctoParser as ParserTrait<Rule>>::parse(Rule::prog, text)
+        .map_err(|e| Error::Internal(format!("Could not split Octo source into tokens: {e}")))?
+        .next()
+        .unwrap();
+    let tokens = prog
+        .into_inner()
+        .filter(|p| p.as_rule() != Rule::EOI)
+        .map(|p| Token {
+            rule: p.as_rule(),
+            span: p.as_span(),
+        })
+        .collect();
+    let mut lowering = Lowering {
+        tokens,
+        eof: Span::new(text, text.len(), text.len()).unwrap(),
+        items: Vec::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        constants: HashMap::new(),
+        blocks: Vec::new(),
+        next_id: 0,
+        expansions: 0,
+    };
+    lowering.program()?;
+    Ok(lowering.items)
+}
+
+struct Lowering<'a> {
+    tokens: VecDeque<Token<'a>>,
+    /// An empty span at the end of the source, for errors about missing
+    /// tokens.
+    eof: Span<'a>,
+    items: Vec<(Item<'a>, Span<'a>)>,
+    aliases: HashMap<&'a str, Register>,
+    macros: HashMap<&'a str, Macro<'a>>,
+    /// Constants whose values are known where they are defined, for `:org`.
+    constants: HashMap<&'a str, i64>,
+    blocks: Vec<Block<'a>>,
+    next_id: usize,
+    expansions: usize,
+}
+
+impl<'a> Lowering<'a> {
+    fn program(&mut self) -> Result<()> {
+        // Execution starts at `main`, so jump there unless it comes first.
+        let first: Vec<&str> = self.tokens.iter().take(2).map(Token::text).collect();
+        if first != [":", "main"] {
+            let span = self.tokens.front().map_or_else(|| self.eof, |t| t.span);
+            self.inst(
+                Instruction::JpAbs {
+                    addr: symbol("main"),
+                },
+                span,
+            );
+        }
+        while let Some(token) = self.tokens.pop_front() {
+            self.statement(token)?;
+        }
+        match self.blocks.pop() {
+            Some(Block::If { start, .. } | Block::Loop { start, .. }) => {
+                Err(Error::Unclosed(start.as_str().into()).at(start))
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn statement(&mut self, token: Token<'a>) -> Result<()> {
+        use Instruction::*;
+        let span = token.span;
+        match token.text() {
+            ":" => {
+                let name = self.name()?;
+                self.label(name.text().to_string(), name.span);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let (value, _) = self.value()?;
+                self.constant(name, value);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let (value, _) = self.calc()?;
+                self.constant(name, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let reg = self.register()?;
+                self.aliases.insert(name.text(), reg);
+            }
+            ":macro" => self.define_macro()?,
+            ":byte" => {
+                let (value, span) = match self.tokens.front().map(Token::text) {
+                    Some("{") => self.calc()?,
+                    _ => self.value()?,
+                };
+                let byte = Immediate::from_expr(value, 0xFF).map_err(|e| e.at(span))?;
+                self.items.push((Item::Bytes(vec![(byte, span)]), span));
+            }
+            ":org" => {
+                let (value, span) = self.value()?;
+                let constants = &self.constants;
+                let addr = value
+                    .eval_bounded(0xFFFF, &mut |name| {
+                        constants
+                            .get(name)
+                            .copied()
+                            .ok_or_else(|| Error::UnresolvedLabel(name.into()))
+                    })
+                    .map_err(|e| e.at(span))?;
+                self.items.push((Item::Org(addr), span));
+            }
+            ":call" => {
+                let (addr, span) = self.address()?;
+                self.inst(Call { addr }, span);
+            }
+            ":unpack" => {
+                // Loads the nibble into the top of one register and the
+                // address into the rest of it and the next.
+                let (nibble, _) = self.value()?;
+                let (addr, span) = self.value()?;
+                let high = Expr::Binary(
+                    BinaryOp::Or,
+                    Box::new(Expr::Binary(
+                        BinaryOp::Shl,
+                        Box::new(nibble),
+                        Box::new(Expr::Num(4)),
+                    )),
+                    Box::new(Expr::Unary(UnaryOp::HighByte, Box::new(addr.clone()))),
+                );
+                let low = Expr::Unary(UnaryOp::LowByte, Box::new(addr));
+                for (alias, default, value) in [
+                    ("unpack-hi", Register::V0, high),
+                    ("unpack-lo", Register::V1, low),
+                ] {
+                    let imm = Immediate::from_expr(value, 0xFF).map_err(|e| e.at(span))?;
+                    let reg = self.alias_or(alias, default);
+                    self.inst(LdImm { reg, imm }, span);
+                }
+            }
+            // Debugger directives have no effect on the program.
+            ":breakpoint" => {
+                self.take("a breakpoint name")?;
+            }
+            ":monitor" => {
+                self.take("an address")?;
+                self.take("a length or format")?;
+            }
+            "return" | ";" => self.inst(Ret, span),
+            "clear" => self.inst(Cls, span),
+            "bcd" => {
+                let reg = self.register()?;
+                self.inst(LdBcd { reg }, span);
+            }
+            "save" | "load" => {
+                let first = self.register()?;
+                let inst = match self.tokens.front().map(Token::text) {
+                    Some("-") => {
+                        self.tokens.pop_front();
+                        let last = self.register()?;
+                        match token.text() {
+                            "save" => Save { first, last },
+                            _ => Load { first, last },
+                        }
+                    }
+                    _ => match token.text() {
+                        "save" => LdRegDump { reg: first },
+                        _ => LdRegRead { reg: first },
+                    },
+                };
+                self.inst(inst, span);
+            }
+            "saveflags" => {
+                let reg = self.register()?;
+                self.inst(LdRplDump { reg }, span);
+            }
+            "loadflags" => {
+                let reg = self.register()?;
+                self.inst(LdRplRead { reg }, span);
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let (nibble, span) = self.imm(0xF)?;
+                self.inst(Drw { x, y, nibble }, span);
+            }
+            "jump" => {
+                let (addr, span) = self.address()?;
+                self.inst(JpAbs { addr }, span);
+            }
+            "jump0" => {
+                let (addr, span) = self.address()?;
+                self.inst(JpRel { addr }, span);
+            }
+            "native" => {
+                let (addr, span) = self.address()?;
+                self.inst(Sys { addr }, span);
+            }
+            "hires" => self.inst(High, span),
+            "lores" => self.inst(Low, span),
+            "exit" => self.inst(Exit, span),
+            "scroll-down" => {
+                let (nibble, span) = self.imm(0xF)?;
+                self.inst(Scd { nibble }, span);
+            }
+            "scroll-up" => {
+                let (nibble, span) = self.imm(0xF)?;
+                self.inst(Scu { nibble }, span);
+            }
+            "scroll-left" => self.inst(Scl, span),
+            "scroll-right" => self.inst(Scr, span),
+            "audio" => self.inst(Audio, span),
+            "plane" => {
+                let (mask, span) = self.imm(0xF)?;
+                self.inst(Plane { mask }, span);
+            }
+            "i" => self.index(span)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let reg = self.register()?;
+                let inst = match token.text() {
+                    "delay" => LdSetDt { reg },
+                    "buzzer" => LdSetSt { reg },
+                    _ => LdPitch { reg },
+                };
+                self.inst(inst, span);
+            }
+            "if" => self.if_statement(span)?,
+            "else" => match self.blocks.last_mut() {
+                Some(Block::If { id, has_else, .. }) if !*has_else => {
+                    *has_else = true;
+                    let id = *id;
+                    self.inst(
+                        JpAbs {
+                            addr: symbol(&format!("end#{id}")),
+                        },
+                        span,
+                    );
+                    self.label(format!("else#{id}"), span);
+                }
+                _ => return Err(Error::Unmatched("else".into(), "begin".into()).at(span)),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { id, has_else, .. }) => {
+                    let target = if has_else { "end" } else { "else" };
+                    self.label(format!("{target}#{id}"), span);
+                }
+                _ => return Err(Error::Unmatched("end".into(), "begin".into()).at(span)),
+            },
+            "loop" => {
+                let id = self.id();
+                self.label(format!("loop#{id}"), span);
+                self.blocks.push(Block::Loop { id, start: span });
+            }
+            "while" => {
+                let id = self.blocks.iter().rev().find_map(|block| match block {
+                    Block::Loop { id, .. } => Some(*id),
+                    _ => None,
+                });
+                let id =
+                    id.ok_or_else(|| Error::Unmatched("while".into(), "loop".into()).at(span))?;
+                self.conditional(true)?;
+                self.inst(
+                    JpAbs {
+                        addr: symbol(&format!("again#{id}")),
+                    },
+                    span,
+                );
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { id, .. }) => {
+                    self.inst(
+                        JpAbs {
+                            addr: symbol(&format!("loop#{id}")),
+                        },
+                        span,
+                    );
+                    self.label(format!("again#{id}"), span);
+                }
+                _ => return Err(Error::Unmatched("again".into(), "loop".into()).at(span)),
+            },
+            _ if token.rule == Rule::number => {
+                let byte =
+                    Immediate::from_expr(self.number(&token)?, 0xFF).map_err(|e| e.at(span))?;
+                self.items.push((Item::Bytes(vec![(byte, span)]), span));
+            }
+            _ if self.macros.contains_key(token.text()) => self.expand(token)?,
+            _ => match self.as_register(&token) {
+                Some(reg) => self.assignment(reg)?,
+                None if is_name(token.text()) => self.inst(
+                    Call {
+                        addr: symbol(token.text()),
+                    },
+                    span,
+                ),
+                None => return Err(expected("a statement", &token)),
+            },
+        }
+        Ok(())
+    }
+
+    /// Lowers the rest of a statement that starts with register `reg`.
+    fn assignment(&mut self, reg: Register) -> Result<()> {
+        use Instruction::*;
+        let op = self.take("an assignment operator")?;
+        let span = op.span;
+        let inst = match op.text() {
+            ":=" => match self.tokens.front().map(Token::text) {
+                Some("random") => {
+                    self.tokens.pop_front();
+                    let (imm, span) = self.imm(0xFF)?;
+                    self.inst(Rnd { reg, imm }, span);
+                    return Ok(());
+                }
+                Some("key") => {
+                    self.tokens.pop_front();
+                    LdKey { reg }
+                }
+                Some("delay") => {
+                    self.tokens.pop_front();
+                    LdReadDt { reg }
+                }
+                _ => match self.operand()? {
+                    Operand::Register(src) => LdReg { dest: reg, src },
+                    Operand::Value(value, span) => {
+                        let imm = Immediate::from_expr(value, 0xFF).map_err(|e| e.at(span))?;
+                        self.inst(LdImm { reg, imm }, span);
+                        return Ok(());
+                    }
+                },
+            },
+            "+=" | "-=" => match self.operand()? {
+                Operand::Register(src) if op.text() == "+=" => AddReg { dest: reg, src },
+                Operand::Register(src) => Sub { dest: reg, src },
+                Operand::Value(value, span) => {
+                    let value = match op.text() {
+                        "+=" => value,
+                        _ => Expr::Unary(UnaryOp::Neg, Box::new(value)),
+                    };
+                    let imm = Immediate::from_expr(value, 0xFF).map_err(|e| e.at(span))?;
+                    self.inst(AddImm { reg, imm }, span);
+                    return Ok(());
+                }
+            },
+            "=-" | "|=" | "&=" | "^=" | ">>=" | "<<=" => {
+                let src = self.register()?;
+                match op.text() {
+                    "=-" => SubN { dest: reg, src },
+                    "|=" => Or { dest: reg, src },
+                    "&=" => And { dest: reg, src },
+                    "^=" => Xor { dest: reg, src },
+                    ">>=" => Shr { dest: reg, src },
+                    _ => Shl { dest: reg, src },
+                }
+            }
+            _ => return Err(expected("an assignment operator", &op)),
+        };
+        self.inst(inst, span);
+        Ok(())
+    }
+
+    /// Lowers the rest of a statement that starts with `i`.
+    fn index(&mut self, span: Span<'a>) -> Result<()> {
+        use Instruction::*;
+        let op = self.take("':=' or '+='")?;
+        match op.text() {
+            ":=" => match self.tokens.front().map(Token::text) {
+                Some("hex" | "bighex") => {
+                    let big = self.tokens.pop_front().unwrap().text() == "bighex";
+                    let reg = self.register()?;
+                    match big {
+                        true => self.inst(LdBigSprite { reg }, span),
+                        false => self.inst(LdSprite { reg }, span),
+                    }
+                }
+                Some("long") => {
+                    self.tokens.pop_front();
+                    let (addr, span) = self.imm(0xFFFF)?;
+                    self.inst(LdLong { addr }, span);
+                }
+                _ => {
+                    let (addr, span) = self.address()?;
+                    self.inst(LdAddr { addr }, span);
+                }
+            },
+            "+=" => {
+                let reg = self.register()?;
+                self.inst(AddI { reg }, span);
+            }
+            _ => return Err(expected("':=' or '+='", &op)),
+        }
+        Ok(())
+    }
+
+    fn if_statement(&mut self, span: Span<'a>) -> Result<()> {
+        // Key tests are two tokens long and comparisons three.
+        let len = match self.tokens.get(1).map(Token::text) {
+            Some("key" | "-key") => 2,
+            _ => 3,
+        };
+        match self.tokens.get(len).map(Token::text) {
+            Some("then") => {
+                self.conditional(false)?;
+                self.tokens.pop_front();
+            }
+            Some("begin") => {
+                self.conditional(true)?;
+                self.tokens.pop_front();
+                let id = self.id();
+                self.inst(
+                    Instruction::JpAbs {
+                        addr: symbol(&format!("else#{id}")),
+                    },
+                    span,
+                );
+                self.blocks.push(Block::If {
+                    id,
+                    has_else: false,
+                    start: span,
+                });
+            }
+            _ => {
+                return Err(match self.tokens.get(len) {
+                    Some(token) => expected("'then' or 'begin'", token),
+                    None => self.end_of_file("'then' or 'begin'"),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits the instructions for a condition. Unless `negated` is set, the
+    /// last of them skips the next instruction when the condition is false.
+    fn conditional(&mut self, negated: bool) -> Result<()> {
+        use Instruction::*;
+        let reg = self.register()?;
+        let cmp = self.take("a comparison")?;
+        let op = match (cmp.text(), negated) {
+            ("==", true) => "!=",
+            ("!=", true) => "==",
+            ("key", true) => "-key",
+            ("-key", true) => "key",
+            ("<", true) => ">=",
+            (">", true) => "<=",
+            (">=", true) => "<",
+            ("<=", true) => ">",
+            (op, _) => op,
+        };
+        let span = cmp.span;
+        match op {
+            "key" => self.inst(Sknp { reg }, span),
+            "-key" => self.inst(Skp { reg }, span),
+            "==" | "!=" => {
+                let inst = match (self.operand()?, op) {
+                    (Operand::Register(src), "==") => SneReg {
+                        reg0: reg,
+                        reg1: src,
+                    },
+                    (Operand::Register(src), _) => SeReg {
+                        reg0: reg,
+                        reg1: src,
+                    },
+                    (Operand::Value(value, span), op) => {
+                        let imm = Immediate::from_expr(value, 0xFF).map_err(|e| e.at(span))?;
+                        let inst = match op {
+                            "==" => SneImm { reg, imm },
+                            _ => SeImm { reg, imm },
+                        };
+                        self.inst(inst, span);
+                        return Ok(());
+                    }
+                };
+                self.inst(inst, span);
+            }
+            // Ordered comparisons subtract in a scratch register and test the
+            // borrow flag.
+            "<" | ">" | "<=" | ">=" => {
+                let temp = self.alias_or("compare-temp", Register::VF);
+                match self.operand()? {
+                    Operand::Register(src) => self.inst(LdReg { dest: temp, src }, span),
+                    Operand::Value(value, span) => {
+                        let imm = Immediate::from_expr(value, 0xFF).map_err(|e| e.at(span))?;
+                        self.inst(LdImm { reg: temp, imm }, span);
+                    }
+                }
+                let subtract = match op {
+                    ">" | "<=" => Sub {
+                        dest: temp,
+                        src: reg,
+                    },
+                    _ => SubN {
+                        dest: temp,
+                        src: reg,
+                    },
+                };
+                self.inst(subtract, span);
+                let imm = Immediate::Value(1);
+                let reg = Register::VF;
+                match op {
+                    ">" | "<" => self.inst(SeImm { reg, imm }, span),
+                    _ => self.inst(SneImm { reg, imm }, span),
+                }
+            }
+            _ => return Err(expected("a comparison", &cmp)),
+        }
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<()> {
+        let name = self.name()?;
+        let mut params = Vec::new();
+        let open = loop {
+            let token = self.take("'{'")?;
+            if token.text() == "{" {
+                break token;
+            }
+            params.push(token.text());
+        };
+        let body = self.braced(&open)?;
+        self.macros.insert(name.text(), Macro { params, body });
+        Ok(())
+    }
+
+    /// Replaces a macro call with the macro's body, with its parameters
+    /// replaced by the tokens that follow the call.
+    fn expand(&mut self, call: Token<'a>) -> Result<()> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(Error::RecursiveMacro(call.text().into()).at(call.span));
+        }
+        let params = self.macros[call.text()].params.clone();
+        let mut args = HashMap::new();
+        for param in params {
+            args.insert(param, self.take("a macro argument")?);
+        }
+        let body = &self.macros[call.text()].body;
+        for token in body.iter().rev() {
+            let token = args.get(token.text()).unwrap_or(token).clone();
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    /// Reads a `{ ... }` expression. Octo has no operator precedence:
+    /// expressions are evaluated from right to left.
+    fn calc(&mut self) -> Result<(Expr, Span<'a>)> {
+        let open = self.expect("{")?;
+        let mut tokens: VecDeque<_> = self.braced(&open)?.into();
+        let expr = self.calc_expr(&mut tokens, &open.span)?;
+        match tokens.pop_front() {
+            Some(token) => Err(expected("an operator", &token)),
+            None => Ok((expr, open.span)),
+        }
+    }
+
+    fn calc_expr(&self, tokens: &mut VecDeque<Token<'a>>, open: &Span<'a>) -> Result<Expr> {
+        let missing = || {
+            Error::Expected {
+                expected: "a number or name".into(),
+                found: "}".into(),
+            }
+            .at(*open)
+        };
+        let token = tokens.pop_front().ok_or_else(missing)?;
+        let lhs = match token.text() {
+            "(" => {
+                let inner = self.calc_expr(tokens, open)?;
+                match tokens.pop_front() {
+                    Some(close) if close.text() == ")" => inner,
+                    Some(other) => return Err(expected("')'", &other)),
+                    None => return Err(Error::Unclosed("(".into()).at(token.span)),
+                }
+            }
+            "-" | "~" => {
+                let op = match token.text() {
+                    "-" => UnaryOp::Neg,
+                    _ => UnaryOp::Not,
+                };
+                return Ok(Expr::Unary(op, Box::new(self.calc_expr(tokens, open)?)));
+            }
+            _ => self.expr(&token)?,
+        };
+        let op = match tokens.front().map(Token::text) {
+            Some("+") => BinaryOp::Add,
+            Some("-") => BinaryOp::Sub,
+            Some("*") => BinaryOp::Mul,
+            Some("/") => BinaryOp::Div,
+            Some("%") => BinaryOp::Mod,
+            Some("<<") => BinaryOp::Shl,
+            Some(">>") => BinaryOp::Shr,
+            Some("&") => BinaryOp::And,
+            Some("|") => BinaryOp::Or,
+            Some("^") => BinaryOp::Xor,
+            _ => return Ok(lhs),
+        };
+        tokens.pop_front();
+        let rhs = self.calc_expr(tokens, open)?;
+        Ok(Expr::Binary(op, Box::new(lhs), Box::new(rhs)))
+    }
+
+    /// Collects the tokens up to the `}` that closes `open`.
+    fn braced(&mut self, open: &Token<'a>) -> Result<Vec<Token<'a>>> {
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self
+                .tokens
+                .pop_front()
+                .ok_or_else(|| Error::Unclosed("{".into()).at(open.span))?;
+            match token.text() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => (),
+            }
+            body.push(token);
+        }
+    }
+
+    fn take(&mut self, expected: &str) -> Result<Token<'a>> {
+        self.tokens
+            .pop_front()
+            .ok_or_else(|| self.end_of_file(expected))
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token<'a>> {
+        let quoted = format!("'{text}'");
+        let token = self.take(&quoted)?;
+        match token.text() == text {
+            true => Ok(token),
+            false => Err(expected(&quoted, &token)),
+        }
+    }
+
+    fn name(&mut self) -> Result<Token<'a>> {
+        let token = self.take("a name")?;
+        match token.rule == Rule::word && is_name(token.text()) {
+            true => Ok(token),
+            false => Err(expected("a name", &token)),
+        }
+    }
+
+    fn register(&mut self) -> Result<Register> {
+        let token = self.take("a register")?;
+        self.as_register(&token)
+            .ok_or_else(|| expected("a register", &token))
+    }
+
+    fn operand(&mut self) -> Result<Operand<'a>> {
+        let token = self.take("a register, number or name")?;
+        match self.as_register(&token) {
+            Some(reg) => Ok(Operand::Register(reg)),
+            None => Ok(Operand::Value(self.expr(&token)?, token.span)),
+        }
+    }
+
+    fn value(&mut self) -> Result<(Expr, Span<'a>)> {
+        let token = self.take("a number or name")?;
+        Ok((self.expr(&token)?, token.span))
+    }
+
+    fn imm(&mut self, max: u16) -> Result<(Immediate, Span<'a>)> {
+        let (value, span) = self.value()?;
+        let imm = Immediate::from_expr(value, max).map_err(|e| e.at(span))?;
+        Ok((imm, span))
+    }
+
+    fn address(&mut self) -> Result<(Address, Span<'a>)> {
+        let (value, span) = self.value()?;
+        let addr = Address::from_expr(value).map_err(|e| e.at(span))?;
+        Ok((addr, span))
+    }
+
+    fn expr(&self, token: &Token<'a>) -> Result<Expr> {
+        match token.rule {
+            Rule::number => self.number(token),
+            Rule::word if is_name(token.text()) && self.as_register(token).is_none() => {
+                Ok(Expr::Symbol(token.text().into()))
+            }
+            _ => Err(expected("a number or name", token)),
+        }
+    }
+
+    fn number(&self, token: &Token<'a>) -> Result<Expr> {
+        let text = token.text();
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text),
+        };
+        let (digits, radix) = match (digits.strip_prefix("0x"), digits.strip_prefix("0b")) {
+            (Some(hex), _) => (hex, 16),
+            (_, Some(bin)) => (bin, 2),
+            _ => (digits, 10),
+        };
+        let val = i64::from_str_radix(digits, radix).map_err(|e| Error::from(e).at(token.span))?;
+        Ok(Expr::Num(if negative { -val } else { val }))
+    }
+
+    fn as_register(&self, token: &Token<'a>) -> Option<Register> {
+        match token.rule {
+            Rule::register => {
+                Register::try_from(u8::from_str_radix(&token.text()[1..], 16).ok()?).ok()
+            }
+            _ => self.aliases.get(token.text()).copied(),
+        }
+    }
+
+    fn alias_or(&self, name: &str, default: Register) -> Register {
+        self.aliases.get(name).copied().unwrap_or(default)
+    }
+
+    fn constant(&mut self, name: Token<'a>, value: Expr) {
+        let constants = &self.constants;
+        if let Ok(val) = value.eval(&mut |name| {
+            constants
+                .get(name)
+                .copied()
+                .ok_or_else(|| Error::UnresolvedLabel(name.into()))
+        }) {
+            self.constants.insert(name.text(), val);
+        }
+        self.items
+            .push((Item::Constant(name.span, value), name.span));
+    }
+
+    fn label(&mut self, name: String, span: Span<'a>) {
+        self.items.push((Item::Label(name, span), span));
+    }
+
+    fn inst(&mut self, inst: Instruction, span: Span<'a>) {
+        self.items
+            .push((Item::Inst(inst, InstSpans::whole(span)), span));
+    }
+
+    fn id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn end_of_file(&self, expected: &str) -> Error {
+        Error::Expected {
+            expected: expected.into(),
+            found: "end of file".into(),
+        }
+        .at(self.eof)
+    }
+}
+
+fn expected(expected: &str, found: &Token<'_>) -> Error {
+    Error::Expected {
+        expected: expected.into(),
+        found: found.text().into(),
+    }
+    .at(found.span)
+}
+
+fn symbol(name: &str) -> Address {
+    Address::Expr(Expr::Symbol(name.into()))
+}
+
+/// Whether `text` can name a label, constant, alias or macro.
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && !KEYWORDS.contains(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::Assembler, assert_ok, quirks::Platform, test_utils::options_for};
+
+    fn assemble(text: &str) -> Result<Vec<u8>> {
+        assemble_for(text, Platform::Modern)
+    }
+
+    fn assemble_for(text: &str, target: Platform) -> Result<Vec<u8>> {
+        let asm = Assembler::build_items(lower(text)?, &options_for(target))?;
+        let mut dest = Vec::new();
+        asm.write_bin(&mut dest)?;
+        Ok(dest)
+    }
+
+    #[test]
+    fn test_program() {
+        let rom = assert_ok!(assemble(include_str!("../test_files/octo.8o")));
+        assert_eq!(
+            rom,
+            [
+                0x12, 0x08, 0xA2, 0x2E, 0xD1, 0x24, 0x00, 0xEE, 0x00, 0xE0, 0x61, 0x00, 0xC2, 0x1F,
+                0x22, 0x02, 0x71, 0x02, 0x6F, 0x38, 0x8F, 0x15, 0x3F, 0x01, 0x61, 0x00, 0x32, 0x00,
+                0x12, 0x22, 0x62, 0x01, 0x12, 0x24, 0x72, 0xFF, 0xF0, 0x0A, 0x40, 0x05, 0x12, 0x2C,
+                0x12, 0x0E, 0x12, 0x08, 0xF0, 0x90, 0x90, 0xF0
+            ]
+        );
+        // No jump is needed when the program starts at main.
+        assert_eq!(assert_ok!(assemble(": main\n\tclear")), [0x00, 0xE0]);
+
+        // The labels loops and conditionals jump to are not exported.
+        let items = assert_ok!(lower(include_str!("../test_files/octo.8o")));
+        let asm = assert_ok!(Assembler::build_items(
+            items,
+            &options_for(Platform::Modern)
+        ));
+        let symbols = asm.symbols();
+        assert!(
+            symbols.iter().all(|(_, name)| !name.contains('#')),
+            "{symbols:?}"
+        );
+        assert!(symbols.contains(&(0x208, "main")), "{symbols:?}");
+    }
+
+    #[test]
+    fn test_conditions() {
+        let text = "
+            : main
+            if v1 == v2 then return
+            if v1 != 3 then return
+            if v1 key then return
+            if v1 -key then return
+            if v1 < 4 then return
+            if v1 >= v3 then return
+        ";
+        assert_eq!(
+            assert_ok!(assemble(text)),
+            [
+                0x91, 0x20, 0x00, 0xEE, 0x31, 0x03, 0x00, 0xEE, 0xE1, 0xA1, 0x00, 0xEE, 0xE1, 0x9E,
+                0x00, 0xEE, 0x6F, 0x04, 0x8F, 0x17, 0x3F, 0x01, 0x00, 0xEE, 0x8F, 0x30, 0x8F, 0x17,
+                0x4F, 0x01, 0x00, 0xEE
+            ]
+        );
+    }
+
+    #[test]
+    fn test_directives() {
+        let text = "
+            : main
+            :const BASE 0x300
+            :calc OFFSET { 2 * ( 1 + 3 ) - 1 }
+            :unpack 0xA data
+            :byte { OFFSET }
+            :call data
+            :breakpoint here
+            :org BASE
+            : data
+            i := long data
+            plane 3
+            scroll-up 2
+            save v1 - v3
+            pitch := v0
+        ";
+        let rom = assert_ok!(assemble_for(text, Platform::XoChip));
+        // OFFSET is evaluated right to left, as 2 * ((1 + 3) - 1).
+        assert_eq!(rom[..7], [0x60, 0xA3, 0x61, 0x00, 0x06, 0x23, 0x00]);
+        assert_eq!(
+            rom[0x100..],
+            [0xF0, 0x00, 0x03, 0x00, 0xF3, 0x01, 0x00, 0xD2, 0x51, 0x32, 0xF0, 0x3A]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text: &str| match assemble(text) {
+            Err(e) => e.to_string(),
+            Ok(rom) => panic!("assembled {rom:?}"),
+        };
+        let msg = error(": main\nloop\n  v0 += 1\n");
+        assert!(
+            msg.contains("'loop' is never closed") && msg.contains("2:1"),
+            "{msg}"
+        );
+        let msg = error(": main\nelse\n");
+        assert!(msg.contains("'else' has no matching 'begin'"), "{msg}");
+        let msg = error(": main\nv0 := key key\n");
+        assert!(msg.contains("Expected a statement, found 'key'"), "{msg}");
+        let msg = error(": main\nif v0 == 1 return\n");
+        assert!(msg.contains("Expected 'then' or 'begin'"), "{msg}");
+        let msg = error(": main\nsprite v0 v1\n");
+        assert!(msg.contains("found 'end of file'"), "{msg}");
+        let msg = error(":macro forever { forever }\n: main\nforever\n");
+        assert!(msg.contains("Macro 'forever' expands without end"), "{msg}");
+        let msg = error("clear\n");
+        assert!(msg.contains("Label 'main' is not defined"), "{msg}");
+    }
+}
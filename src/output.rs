@@ -0,0 +1,186 @@
+use std::{fmt, io::prelude::*, str::FromStr};
+
+/// The forms an assembled ROM can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// The raw bytes.
+    #[default]
+    Bin,
+    /// Intel HEX records placing the bytes at their load address.
+    Ihex,
+    /// Rows of 16 bytes in hex and ASCII, each starting with its address.
+    Hexdump,
+    /// A C header defining `static const uint8_t rom[]`, which any number of
+    /// files can include.
+    C,
+    /// A Rust module defining `pub const ROM: [u8; N]`.
+    Rust,
+}
+
+impl OutputFormat {
+    const BYTES_PER_RECORD: usize = 16;
+    const BYTES_PER_LINE: usize = 12;
+
+    /// The extension of output in this format, when no path is given.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Bin => "bin",
+            OutputFormat::Ihex => "hex",
+            OutputFormat::Hexdump => "txt",
+            OutputFormat::C => "h",
+            OutputFormat::Rust => "rs",
+        }
+    }
+
+    /// Writes `rom`, which is loaded at `start`.
+    pub fn write(self, rom: &[u8], start: u16, mut dest: impl Write) -> std::io::Result<()> {
+        match self {
+            OutputFormat::Bin => dest.write_all(rom),
+            OutputFormat::Ihex => {
+                for (i, chunk) in rom.chunks(Self::BYTES_PER_RECORD).enumerate() {
+                    let addr = start.wrapping_add((i * Self::BYTES_PER_RECORD) as u16);
+                    let mut record = vec![chunk.len() as u8];
+                    record.extend(addr.to_be_bytes());
+                    record.push(0x00);
+                    record.extend(chunk);
+                    write_record(&mut dest, &record)?;
+                }
+                write_record(&mut dest, &[0x00, 0x00, 0x00, 0x01])
+            }
+            OutputFormat::Hexdump => {
+                for (i, chunk) in rom.chunks(Self::BYTES_PER_RECORD).enumerate() {
+                    let addr = usize::from(start) + i * Self::BYTES_PER_RECORD;
+                    let hex = chunk
+                        .iter()
+                        .map(|byte| format!("{byte:02X}"))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let text: String = chunk
+                        .iter()
+                        .map(|byte| match byte {
+                            0x20..=0x7E => char::from(*byte),
+                            _ => '.',
+                        })
+                        .collect();
+                    let width = Self::BYTES_PER_RECORD * 3 - 1;
+                    writeln!(dest, "{addr:04X}  {hex:width$}  |{text}|")?;
+                }
+                Ok(())
+            }
+            OutputFormat::C => {
+                writeln!(dest, "#pragma once\n\n#include <stdint.h>\n")?;
+                writeln!(dest, "static const uint8_t rom[{}] = {{", rom.len())?;
+                write_array(&mut dest, rom)?;
+                writeln!(dest, "}};")
+            }
+            OutputFormat::Rust => {
+                writeln!(dest, "pub const ROM: [u8; {}] = [", rom.len())?;
+                write_array(&mut dest, rom)?;
+                writeln!(dest, "];")
+            }
+        }
+    }
+}
+
+/// Writes an Intel HEX record: its count, address, type and data, followed
+/// by the checksum that makes the bytes sum to zero.
+fn write_record(dest: &mut impl Write, record: &[u8]) -> std::io::Result<()> {
+    let sum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    let hex: String = record.iter().map(|byte| format!("{byte:02X}")).collect();
+    writeln!(dest, ":{hex}{:02X}", sum.wrapping_neg())
+}
+
+/// Writes bytes as the lines of an array literal, indented and with a
+/// trailing comma after each.
+fn write_array(dest: &mut impl Write, rom: &[u8]) -> std::io::Result<()> {
+    for chunk in rom.chunks(OutputFormat::BYTES_PER_LINE) {
+        let line: String = chunk.iter().map(|byte| format!(" 0x{byte:02X},")).collect();
+        writeln!(dest, "   {line}")?;
+    }
+    Ok(())
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bin" => Ok(OutputFormat::Bin),
+            "ihex" => Ok(OutputFormat::Ihex),
+            "hexdump" => Ok(OutputFormat::Hexdump),
+            "c" => Ok(OutputFormat::C),
+            "rust" => Ok(OutputFormat::Rust),
+            _ => Err(format!(
+                "'{s}' is not an output format: expected bin, ihex, hexdump, c or rust"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OutputFormat::Bin => "bin",
+            OutputFormat::Ihex => "ihex",
+            OutputFormat::Hexdump => "hexdump",
+            OutputFormat::C => "c",
+            OutputFormat::Rust => "rust",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(format: OutputFormat, rom: &[u8]) -> String {
+        let mut dest = Vec::new();
+        format.write(rom, 0x200, &mut dest).unwrap();
+        String::from_utf8(dest).unwrap()
+    }
+
+    #[test]
+    fn test_formats() {
+        let rom = [0x60, 0x41, 0x12, 0x00];
+        assert_eq!(write(OutputFormat::Bin, &rom).as_bytes(), rom);
+        assert_eq!(
+            write(OutputFormat::Ihex, &rom),
+            ":040200006041120047\n:00000001FF\n"
+        );
+        assert_eq!(
+            write(OutputFormat::Hexdump, &rom),
+            format!("0200  60 41 12 00{:36}  |`A..|\n", "")
+        );
+        assert_eq!(
+            write(OutputFormat::C, &rom),
+            "#pragma once\n\n#include <stdint.h>\n\n\
+             static const uint8_t rom[4] = {\n    0x60, 0x41, 0x12, 0x00,\n};\n"
+        );
+        assert_eq!(
+            write(OutputFormat::Rust, &rom),
+            "pub const ROM: [u8; 4] = [\n    0x60, 0x41, 0x12, 0x00,\n];\n"
+        );
+    }
+
+    #[test]
+    fn test_ihex_records() {
+        let rom: Vec<u8> = (0..20).collect();
+        let hex = write(OutputFormat::Ihex, &rom);
+        let records: Vec<&str> = hex.lines().collect();
+        assert_eq!(records.len(), 3);
+        assert!(records[0].starts_with(":10020000000102"), "{}", records[0]);
+        assert!(
+            records[1].starts_with(":0402100010111213"),
+            "{}",
+            records[1]
+        );
+        for record in records {
+            let bytes: Vec<u8> = (1..record.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&record[i..i + 2], 16).unwrap())
+                .collect();
+            let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            assert_eq!(sum, 0, "{record}");
+        }
+    }
+}
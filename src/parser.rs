@@ -1,19 +1,86 @@
-use std::num::ParseIntError;
+use std::{fmt, num::ParseIntError, path::Path, str::FromStr};
 
 use crate::error::*;
-use pest::{iterators::Pair, Parser as ParserTrait};
+use pest::{error::LineColLocation, iterators::Pair, Parser as ParserTrait};
 use pest_derive::Parser as ParserDerive;
 
+/// The languages chip8c can assemble.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// chip8c's own assembly language, parsed by `Parser`.
+    #[default]
+    Chip8c,
+    /// Octo, lowered by the `octo` module.
+    Octo,
+}
+
+impl Syntax {
+    /// Picks a syntax from a file's extension: Octo for `.8o` files.
+    pub fn for_path(path: &Path) -> Syntax {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("8o") => Syntax::Octo,
+            _ => Syntax::Chip8c,
+        }
+    }
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8c" => Ok(Syntax::Chip8c),
+            "octo" => Ok(Syntax::Octo),
+            _ => Err(format!("'{s}' is not a syntax: expected chip8c or octo")),
+        }
+    }
+}
+
+impl fmt::Display for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Syntax::Chip8c => "chip8c",
+            Syntax::Octo => "octo",
+        })
+    }
+}
+
 #[derive(ParserDerive)]
 #[grammar = "grammar.pest"]
 pub struct Parser;
 
 impl Parser {
     pub fn parse(text: &str) -> Result<impl Iterator<Item = Pair<'_, Rule>>> {
-        Ok(<Parser as ParserTrait<Rule>>::parse(Rule::prog, text)?
-            .next()
-            .unwrap()
-            .into_inner())
+        match <Parser as ParserTrait<Rule>>::parse(Rule::prog, text) {
+            Ok(mut pairs) => Ok(pairs.next().unwrap().into_inner()),
+            Err(e) => Err(Parser::recover(text, e)),
+        }
+    }
+
+    /// Finds the parse errors after `first` by blanking out each line that
+    /// fails to parse and parsing the text again. Line numbers are unchanged,
+    /// so every error still points into the original text.
+    fn recover(text: &str, first: pest::error::Error<Rule>) -> Error {
+        let mut lines: Vec<&str> = text.split_inclusive('\n').collect();
+        let mut errors = Vec::new();
+        let mut next = Some(first);
+        while let Some(e) = next.take() {
+            let line = match e.line_col {
+                LineColLocation::Pos((line, _)) | LineColLocation::Span((line, _), _) => line - 1,
+            };
+            errors.push(Error::from(e));
+            match lines.get(line) {
+                Some(text) if !text.trim().is_empty() => {
+                    lines[line] = &text[text.trim_end_matches(['\r', '\n']).len()..];
+                }
+                _ => break,
+            }
+            next = <Parser as ParserTrait<Rule>>::parse(Rule::prog, &lines.concat()).err();
+        }
+        match errors.len() {
+            1 => errors.pop().unwrap(),
+            _ => Error::Multiple(errors),
+        }
     }
 }
 
@@ -31,24 +98,24 @@ impl ParseImm for u16 {
     }
 }
 
-pub fn parse_imm<T: ParseImm>(p: Pair<'_, Rule>) -> Result<T> {
+pub fn parse_bounded<T: ParseImm>(p: Pair<'_, Rule>, max: u16) -> Result<T> {
     let (base, prefix) = match p.as_rule() {
-        Rule::imm => return parse_imm(p.into_inner().next().unwrap()),
+        Rule::imm => return parse_bounded(p.into_inner().next().unwrap(), max),
         Rule::hex_lit => (16, "0x"),
         Rule::dec_lit => (10, ""),
         Rule::oct_lit => (8, "0"),
         Rule::bin_lit => (2, "0b"),
         other => {
             return Err(Error::Internal(format!(
-                "Passed a pair with rule type {:?} to parse_imm",
+                "Passed a pair with rule type {:?} to parse_bounded",
                 other,
             )));
         }
     };
     let val =
         T::from_str_radix(p.as_str().trim_start_matches(prefix), base).map_err(Error::NumParse)?;
-    if val.into() > 0x0FFF {
-        Err(Error::ExceedBounds(val.into(), 0x0FFF))
+    if val.into() > max {
+        Err(Error::ExceedBounds(val.into().into(), max))
     } else {
         Ok(val)
     }
@@ -0,0 +1,130 @@
+use crate::instruction::Extension;
+use std::{fmt, str::FromStr};
+
+/// Behaviours that CHIP-8 interpreters disagree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `SHR`/`SHL` shift Vy into Vx, rather than shifting Vx in place.
+    pub shift_vy: bool,
+    /// `LD I, Vx` and `LD Vx, I` leave I pointing past the last register.
+    pub load_store_increments_i: bool,
+    /// `JP V0, addr` adds VX, where X is the top nibble of `addr`, not V0.
+    pub jump_vx: bool,
+    /// `AND`, `OR` and `XOR` reset VF to 0.
+    pub logic_resets_vf: bool,
+    /// Sprites that cross the edge of the screen wrap around instead of
+    /// being clipped.
+    pub wrap_sprites: bool,
+    /// `DRW` waits for the next frame, so at most one runs per frame.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    pub const VIP: Quirks = Quirks {
+        shift_vy: true,
+        load_store_increments_i: true,
+        jump_vx: false,
+        logic_resets_vf: true,
+        wrap_sprites: false,
+        display_wait: true,
+    };
+    pub const CHIP48: Quirks = Quirks {
+        shift_vy: false,
+        load_store_increments_i: true,
+        jump_vx: true,
+        logic_resets_vf: false,
+        wrap_sprites: false,
+        display_wait: false,
+    };
+    pub const SCHIP: Quirks = Quirks {
+        shift_vy: false,
+        load_store_increments_i: false,
+        jump_vx: true,
+        logic_resets_vf: false,
+        wrap_sprites: false,
+        display_wait: false,
+    };
+    pub const XOCHIP: Quirks = Quirks {
+        shift_vy: true,
+        load_store_increments_i: true,
+        jump_vx: false,
+        logic_resets_vf: false,
+        wrap_sprites: true,
+        display_wait: false,
+    };
+    pub const MODERN: Quirks = Quirks {
+        shift_vy: false,
+        load_store_increments_i: false,
+        jump_vx: false,
+        logic_resets_vf: false,
+        wrap_sprites: false,
+        display_wait: false,
+    };
+}
+
+/// A machine or interpreter family that programs are written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Platform {
+    Vip,
+    Chip48,
+    Schip,
+    XoChip,
+    #[default]
+    Modern,
+}
+
+impl Platform {
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Vip => Quirks::VIP,
+            Platform::Chip48 => Quirks::CHIP48,
+            Platform::Schip => Quirks::SCHIP,
+            Platform::XoChip => Quirks::XOCHIP,
+            Platform::Modern => Quirks::MODERN,
+        }
+    }
+
+    /// Bytes of memory, which XO-CHIP extends to the full 16-bit address space.
+    pub fn memory_size(self) -> u32 {
+        match self {
+            Platform::XoChip => 0x10000,
+            _ => 0x1000,
+        }
+    }
+
+    pub fn supports(self, extension: Extension) -> bool {
+        match extension {
+            Extension::Schip => matches!(self, Platform::Schip | Platform::XoChip),
+            Extension::XoChip => self == Platform::XoChip,
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vip" => Ok(Platform::Vip),
+            "chip48" => Ok(Platform::Chip48),
+            "schip" => Ok(Platform::Schip),
+            "xochip" => Ok(Platform::XoChip),
+            "modern" => Ok(Platform::Modern),
+            _ => Err(format!(
+                "'{s}' is not a target: expected vip, chip48, schip, xochip or modern"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Platform::Vip => "COSMAC VIP",
+            Platform::Chip48 => "CHIP-48",
+            Platform::Schip => "SUPER-CHIP 1.1",
+            Platform::XoChip => "XO-CHIP",
+            Platform::Modern => "modern interpreters",
+        })
+    }
+}
@@ -1,5 +1,7 @@
 use crate::{error::*, parser::Rule};
 use pest::iterators::Pair;
+use std::fmt;
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Register {
@@ -53,3 +55,9 @@ impl TryFrom<Pair<'_, Rule>> for Register {
         }
     }
 }
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "V{:X}", *self as u8)
+    }
+}
@@ -0,0 +1,1041 @@
+use crate::{
+    assembler::Names,
+    error::*,
+    expr::Expr,
+    parser::{Parser, Rule},
+};
+use pest::{iterators::Pair, Parser as ParserTrait, Span};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Where a statement came from beyond its span: the file it is in and the
+/// macro calls it was expanded from, innermost first.
+#[derive(Debug, Default)]
+pub(crate) struct Origin {
+    path: Option<String>,
+    calls: Vec<Call>,
+    /// The contents of the files that `incbin` directives in the text name.
+    binaries: Vec<(String, Vec<u8>)>,
+    /// The names labels in a macro's body are renamed to in the expansion.
+    renamed: Vec<String>,
+}
+
+impl Origin {
+    /// Names the file an error about one of the statements is in, and adds
+    /// the calls the statement was expanded from.
+    pub fn locate(&self, error: Error) -> Error {
+        let notes: Vec<_> = self.calls.iter().map(|call| call.note.clone()).collect();
+        error.within(self.path.as_deref(), &notes)
+    }
+
+    /// Attaches the location of `span` in one of the statements to a
+    /// warning, the same way `locate` does for errors.
+    pub fn warn(&self, warning: Warning, span: Span<'_>) -> Warning {
+        Warning::Located {
+            report: Box::new(self.note(span, &warning.to_string())),
+            warning: Box::new(warning),
+            notes: self.calls.iter().map(|call| call.note.clone()).collect(),
+        }
+    }
+
+    /// Renders a secondary location in one of the statements, such as a
+    /// previous definition.
+    pub fn note(&self, span: Span<'_>, message: &str) -> pest::error::Error<Rule> {
+        match &self.path {
+            Some(path) => report(message, span).with_path(path),
+            None => report(message, span),
+        }
+    }
+
+    /// The file the statements are in, if they are not in the program's text.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// The macro calls the statements were expanded from, innermost first.
+    pub fn calls(&self) -> &[Call] {
+        &self.calls
+    }
+
+    /// Whether a label defined in the statements is one the expansion they
+    /// are in renamed, which is not part of the program's symbols.
+    pub fn is_renamed(&self, label: &str) -> bool {
+        let label = label.strip_prefix('.').unwrap_or(label);
+        self.renamed.iter().any(|renamed| renamed == label)
+    }
+
+    /// The contents of the file `incbin "name"` includes.
+    pub fn binary(&self, name: &str) -> Option<&[u8]> {
+        self.binaries
+            .iter()
+            .find(|(included, _)| included == name)
+            .map(|(_, bytes)| bytes.as_slice())
+    }
+}
+
+/// A macro call that statements were expanded from.
+#[derive(Debug, Clone)]
+pub(crate) struct Call {
+    pub name: String,
+    /// The file the call is in, if it is not in the program's text.
+    pub path: Option<String>,
+    pub line: usize,
+    pub col: usize,
+    /// The call, rendered for errors about the expanded statements.
+    note: pest::error::Error<Rule>,
+}
+
+/// The origin of statements written in the program itself.
+pub(crate) static TOP_LEVEL: Origin = Origin {
+    path: None,
+    calls: Vec::new(),
+    binaries: Vec::new(),
+    renamed: Vec::new(),
+};
+
+/// A program with its includes read and its macros expanded. Each file and
+/// each expansion is a separate text; they are parsed one at a time and
+/// spliced together.
+#[derive(Debug)]
+pub struct Source {
+    texts: Vec<Text>,
+}
+
+#[derive(Debug)]
+struct Text {
+    /// The text, with macro definitions, includes, calls and lines in
+    /// excluded conditional blocks blanked out.
+    /// Expansions start with blank lines, so their lines have the numbers of
+    /// the lines in the macro's body they were expanded from.
+    text: String,
+    origin: Origin,
+    /// The offset of each include and macro call in `text`, and the index of
+    /// the text it is replaced by.
+    calls: Vec<(usize, usize)>,
+}
+
+#[derive(Debug)]
+struct Macro {
+    params: Vec<String>,
+    /// Labels the body defines, which are renamed in each expansion.
+    labels: Vec<String>,
+    /// The file the macro is defined in, and the number of lines before the
+    /// body.
+    file: Option<PathBuf>,
+    line: usize,
+    body: String,
+    /// Points at the definition, for errors about calls to it.
+    defined: pest::error::Error<Rule>,
+}
+
+/// An `.if` block, as far as the expander can tell which of its lines are
+/// assembled.
+struct Branch {
+    /// Whether the current part of the block is assembled, or `None` if that
+    /// depends on something only assembling tells, such as a label's address.
+    active: Option<bool>,
+    /// Whether an earlier part was assembled, so that `.else`'s is not.
+    taken: Option<bool>,
+}
+
+#[derive(Default)]
+struct Expander {
+    /// Directories searched for included files that are not found next to
+    /// the file including them.
+    search: Vec<PathBuf>,
+    /// The files being read, outermost first.
+    including: Vec<PathBuf>,
+    macros: HashMap<String, Macro>,
+    texts: Vec<Text>,
+    errors: Vec<Error>,
+    /// The constants and global labels defined so far outside excluded
+    /// blocks, for evaluating conditions.
+    constants: HashMap<String, Expr>,
+    labels: HashSet<String>,
+    /// Names defined in blocks whose conditions the expander cannot
+    /// evaluate, so conditions on them are left to the assembler.
+    uncertain: HashSet<String>,
+    branches: Vec<Branch>,
+}
+
+impl Source {
+    /// Reads the program at `path` and the files it includes, which are
+    /// found next to the file including them or in one of `search`, and
+    /// expands every macro call. Lines in conditional blocks that `defines`
+    /// and the program's constants exclude are skipped.
+    pub fn load(path: &Path, search: &[PathBuf], defines: &[(String, i64)]) -> Result<Source> {
+        let text = fs::read_to_string(path).map_err(cannot_read(path))?;
+        let mut expander = Expander {
+            search: search.to_vec(),
+            including: vec![fs::canonicalize(path)?],
+            ..Expander::new(defines)
+        };
+        expander.expand(Some(path), &text, Vec::new(), 0);
+        expander.finish()
+    }
+
+    /// Expands the macros in a program that was not read from a file.
+    /// Included files are found relative to the working directory.
+    pub fn expand(text: &str, defines: &[(String, i64)]) -> Result<Source> {
+        let mut expander = Expander::new(defines);
+        expander.expand(None, text, Vec::new(), 0);
+        expander.finish()
+    }
+
+    /// Parses every text, returning the program's statements in order, each
+    /// with the origin errors about it should name.
+    pub fn statements(&self) -> Result<Vec<(Pair<'_, Rule>, &Origin)>> {
+        let mut statements = Vec::new();
+        let mut errors = Vec::new();
+        self.parse(0, &mut statements, &mut errors);
+        match errors.len() {
+            0 => Ok(statements),
+            1 => Err(errors.pop().unwrap()),
+            _ => Err(Error::Multiple(errors)),
+        }
+    }
+
+    fn parse<'s>(
+        &'s self,
+        index: usize,
+        statements: &mut Vec<(Pair<'s, Rule>, &'s Origin)>,
+        errors: &mut Vec<Error>,
+    ) {
+        let text = &self.texts[index];
+        let mut calls = text.calls.iter().peekable();
+        match Parser::parse(&text.text) {
+            Ok(pairs) => {
+                for p in pairs.take_while(|p| p.as_rule() != Rule::EOF) {
+                    while let Some((_, expansion)) =
+                        calls.next_if(|(offset, _)| *offset < p.as_span().start())
+                    {
+                        self.parse(*expansion, statements, errors);
+                    }
+                    statements.push((p, &text.origin));
+                }
+            }
+            Err(Error::Multiple(many)) => {
+                errors.extend(many.into_iter().map(|e| text.origin.locate(e)))
+            }
+            Err(e) => errors.push(text.origin.locate(e)),
+        }
+        for (_, expansion) in calls {
+            self.parse(*expansion, statements, errors);
+        }
+    }
+}
+
+impl Expander {
+    fn finish(self) -> Result<Source> {
+        let mut errors = self.errors;
+        errors.sort_by_key(Error::line_col);
+        match errors.len() {
+            0 => Ok(Source { texts: self.texts }),
+            1 => Err(errors.pop().unwrap()),
+            _ => Err(Error::Multiple(errors)),
+        }
+    }
+
+    fn new(defines: &[(String, i64)]) -> Expander {
+        Expander {
+            constants: defines
+                .iter()
+                .map(|(name, value)| (name.clone(), Expr::Num(*value)))
+                .collect(),
+            ..Expander::default()
+        }
+    }
+
+    /// Records a macro defined in the text at `index`.
+    fn add(
+        &mut self,
+        file: Option<&Path>,
+        index: usize,
+        name: Span<'_>,
+        params: Vec<String>,
+        line: usize,
+        body: String,
+    ) {
+        let origin = &self.texts[index].origin;
+        if let Some(first) = self.macros.get(name.as_str()) {
+            self.errors.push(
+                origin.locate(
+                    Error::DuplicateMacro(name.as_str().into())
+                        .at(name)
+                        .with_notes(std::slice::from_ref(&first.defined)),
+                ),
+            );
+            return;
+        }
+        // Local labels are renamed too, so that expanding the macro twice under
+        // one global label does not define them twice.
+        let labels = blank_comments(&body)
+            .lines()
+            .map(|line| code(line).trim_start())
+            .map(|line| first_word(line.strip_prefix('.').unwrap_or(line)))
+            .filter(|(word, rest)| is_name(word) && rest.trim_start().starts_with(':'))
+            .map(|(word, _)| word.to_string())
+            .collect();
+        let defined = origin.note(name, &format!("macro '{}' is defined here", name.as_str()));
+        self.macros.insert(
+            name.as_str().to_string(),
+            Macro {
+                params,
+                labels,
+                file: file.map(Path::to_path_buf),
+                line,
+                body,
+                defined,
+            },
+        );
+    }
+
+    /// Adds `text` with every include and macro call in it replaced by the
+    /// text it stands for, returning its index. Macros are defined line by
+    /// line, and nothing is read or defined in a block that is certainly
+    /// excluded. `depth` counts the expansions `text` is nested in.
+    fn expand(&mut self, file: Option<&Path>, text: &str, calls: Vec<Call>, depth: usize) -> usize {
+        let index = self.texts.len();
+        self.texts.push(Text {
+            text: String::new(),
+            origin: Origin {
+                path: file.map(display),
+                calls,
+                binaries: Vec::new(),
+                renamed: Vec::new(),
+            },
+            calls: Vec::new(),
+        });
+        let mut out = String::with_capacity(text.len());
+        let mut calls = Vec::new();
+        // The macro being defined, its name's span and the line it starts on.
+        let mut open: Option<(Span<'_>, Vec<String>, usize, String)> = None;
+        // Lines are read with their block comments blanked out, and kept as
+        // written.
+        let scan = blank_comments(text);
+        let lines = text.split_inclusive('\n').zip(scan.split_inclusive('\n'));
+        for (number, (line, scanned)) in lines.enumerate() {
+            let (word, rest) = first_word(code(scanned));
+            let keyword = word.to_ascii_lowercase();
+            let starts_macro = keyword == "macro" && !rest.trim_start().starts_with(':');
+            match open.take() {
+                Some((name, params, start, body)) if keyword == "endm" => {
+                    self.add(file, index, name, params, start, body);
+                    out.push_str(&blank(line, scanned));
+                    continue;
+                }
+                Some((name, ..)) if starts_macro => {
+                    let e = Error::Unclosed(format!("macro {}", name.as_str())).at(name);
+                    self.errors.push(self.texts[index].origin.locate(e));
+                }
+                Some(mut definition) => {
+                    definition.3.push_str(line);
+                    open = Some(definition);
+                    out.push_str(&blank(line, scanned));
+                    continue;
+                }
+                None => {}
+            }
+            // Conditional directives stay in the text, so that the assembler
+            // matches each `.else` and `.endif` with its `.if`.
+            let condition = match code(scanned).trim_start() {
+                code if code.starts_with('.') => statement(code),
+                _ => None,
+            };
+            if let Some(p) = condition.filter(|p| is_condition(p.as_rule())) {
+                self.branch(p);
+                out.push_str(line);
+                continue;
+            }
+            if self.excluded() {
+                out.push_str(&blank(line, scanned));
+                continue;
+            }
+            let replaced =
+                match keyword.as_str() {
+                    "macro" if starts_macro => {
+                        match definition(text, &scan, rest) {
+                            Ok((name, params)) => {
+                                open = Some((name, params, number + 1, String::new()))
+                            }
+                            Err(e) => self.errors.push(self.texts[index].origin.locate(e)),
+                        }
+                        None
+                    }
+                    "endm" => Some(Err(Error::Unmatched(word.into(), "macro".into())
+                        .at(span_of(text, &scan, word)))),
+                    _ if rest.trim_start().starts_with(':') => {
+                        self.record(code(scanned));
+                        out.push_str(line);
+                        continue;
+                    }
+                    "include" => Some(
+                        quoted(text, &scan, rest)
+                            .and_then(|(name, span)| self.include(file, name, span, index, depth)),
+                    ),
+                    "incbin" => {
+                        match quoted(text, &scan, rest).and_then(|(name, span)| {
+                            Ok((name.into(), self.incbin(file, name, span)?))
+                        }) {
+                            Ok(binary) => self.texts[index].origin.binaries.push(binary),
+                            Err(e) => self.errors.push(self.texts[index].origin.locate(e)),
+                        }
+                        // The assembler places the bytes where the line is.
+                        out.push_str(line);
+                        continue;
+                    }
+                    _ if self.macros.contains_key(word) => {
+                        let span = span_of(text, &scan, code(scanned).trim());
+                        Some(self.call(word, rest, span, index, depth))
+                    }
+                    _ => {
+                        self.record(code(scanned));
+                        out.push_str(line);
+                        continue;
+                    }
+                };
+            match replaced {
+                None => {}
+                Some(Ok(replacement)) => calls.push((out.len(), replacement)),
+                Some(Err(e)) => {
+                    let e = self.texts[index].origin.locate(e);
+                    self.errors.push(e);
+                }
+            }
+            out.push_str(&blank(line, scanned));
+        }
+        if let Some((name, ..)) = open {
+            let e = Error::Unclosed(format!("macro {}", name.as_str())).at(name);
+            self.errors.push(self.texts[index].origin.locate(e));
+        }
+        self.texts[index].text = out;
+        self.texts[index].calls = calls;
+        index
+    }
+
+    /// Whether the current line is in a block that is certainly excluded.
+    fn excluded(&self) -> bool {
+        self.branches
+            .iter()
+            .any(|branch| branch.active == Some(false))
+    }
+
+    /// Follows a conditional directive, evaluating its condition if every
+    /// name it refers to is certainly defined (or not) by now.
+    fn branch(&mut self, p: Pair<'_, Rule>) {
+        match p.as_rule() {
+            Rule::cond_else => {
+                if let Some(branch) = self.branches.last_mut() {
+                    branch.active = branch.taken.map(|taken| !taken);
+                    branch.taken = branch.taken.map(|_| true);
+                }
+            }
+            Rule::cond_endif => {
+                self.branches.pop();
+            }
+            // As in the assembler, conditions in an excluded block are not
+            // evaluated.
+            _ if self.excluded() => self.branches.push(Branch {
+                active: Some(false),
+                taken: Some(true),
+            }),
+            _ => {
+                let active = self.condition(p).ok();
+                self.branches.push(Branch {
+                    active,
+                    taken: active,
+                });
+            }
+        }
+    }
+
+    /// Notes the global label or constant a line defines, for evaluating
+    /// conditions after it.
+    fn record(&mut self, code: &str) {
+        let p = match statement(code) {
+            Some(p) => p,
+            None => return,
+        };
+        let (name, value) = match p.as_rule() {
+            Rule::label_inner => (p.as_str().to_string(), None),
+            Rule::const_equ | Rule::const_define => {
+                let mut inner = p.into_inner();
+                let name = inner.next().unwrap().as_str().to_string();
+                (name, Expr::try_from(inner.next().unwrap()).ok())
+            }
+            _ => return,
+        };
+        if self.branches.iter().any(|branch| branch.active.is_none()) {
+            self.uncertain.insert(name);
+        } else if let Some(value) = value {
+            self.constants.insert(name, value);
+        } else {
+            self.labels.insert(name);
+        }
+    }
+
+    /// Reads the file `include "name"` names, from the text at `includer`.
+    fn include(
+        &mut self,
+        file: Option<&Path>,
+        name: &str,
+        span: Span<'_>,
+        includer: usize,
+        depth: usize,
+    ) -> Result<usize> {
+        let path = self.find(file, name);
+        let text = fs::read_to_string(&path).map_err(|e| cannot_read(&path)(e).at(span))?;
+        let canonical = fs::canonicalize(&path)?;
+        if self.including.contains(&canonical) {
+            return Err(Error::IncludeCycle(name.into()).at(span));
+        }
+        self.including.push(canonical);
+        let calls = self.texts[includer].origin.calls.clone();
+        let index = self.expand(Some(&path), &text, calls, depth);
+        self.including.pop();
+        Ok(index)
+    }
+
+    /// Reads the file `incbin "name"` names.
+    fn incbin(&self, file: Option<&Path>, name: &str, span: Span<'_>) -> Result<Vec<u8>> {
+        let path = self.find(file, name);
+        fs::read(&path).map_err(|e| cannot_read(&path)(e).at(span))
+    }
+
+    /// Finds a file named from `file`: next to it if it is there, otherwise
+    /// in the first search directory that has it.
+    fn find(&self, file: Option<&Path>, name: &str) -> PathBuf {
+        let local = match file.and_then(Path::parent) {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        };
+        if local.exists() {
+            return local;
+        }
+        self.search
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.exists())
+            .unwrap_or(local)
+    }
+
+    /// Expands a call to the macro `name` made from the text at `caller`.
+    fn call(
+        &mut self,
+        name: &str,
+        args: &str,
+        span: Span<'_>,
+        caller: usize,
+        depth: usize,
+    ) -> Result<usize> {
+        let called = &self.macros[name];
+        // A chain of calls longer than the number of macros must contain a
+        // cycle.
+        if depth >= self.macros.len() {
+            return Err(Error::RecursiveMacro(name.into()).at(span));
+        }
+        let args = split_args(args);
+        if args.len() != called.params.len() {
+            return Err(Error::MacroArgs {
+                name: name.into(),
+                expected: called.params.len(),
+                found: args.len(),
+            }
+            .at(span)
+            .with_notes(std::slice::from_ref(&called.defined)));
+        }
+        // Expansions are numbered by their index, which makes renamed labels
+        // unique.
+        let number = self.texts.len();
+        let mut replacements: HashMap<&str, String> = called
+            .params
+            .iter()
+            .map(String::as_str)
+            .zip(args.iter().map(|arg| arg.to_string()))
+            .collect();
+        let renamed: Vec<_> = called
+            .labels
+            .iter()
+            .map(|label| format!("{label}__{number}"))
+            .collect();
+        replacements.extend(
+            called
+                .labels
+                .iter()
+                .map(String::as_str)
+                .zip(renamed.clone()),
+        );
+        let body = "\n".repeat(called.line) + &substitute(&called.body, &replacements);
+        let file = called.file.clone();
+        let caller = &self.texts[caller].origin;
+        let (line, col) = span.start_pos().line_col();
+        let mut calls = vec![Call {
+            name: name.into(),
+            path: caller.path.clone(),
+            line,
+            col,
+            note: caller.note(span, &format!("in expansion of macro '{name}'")),
+        }];
+        calls.extend(caller.calls.iter().cloned());
+        let index = self.expand(file.as_deref(), &body, calls, depth + 1);
+        self.texts[index].origin.renamed = renamed;
+        Ok(index)
+    }
+}
+
+/// Names the file in an error reading it.
+fn cannot_read(path: &Path) -> impl FnOnce(std::io::Error) -> Error + '_ {
+    |error| Error::Read {
+        path: display(path),
+        error,
+    }
+}
+
+fn display(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Parses the quoted path that follows `include` or `incbin`.
+fn quoted<'t, 's>(text: &'t str, scan: &'s str, rest: &'s str) -> Result<(&'s str, Span<'t>)> {
+    let rest = rest.trim();
+    match rest.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(name) if !name.is_empty() => Ok((name, span_of(text, scan, rest))),
+        _ => Err(Error::Expected {
+            expected: "a quoted path".into(),
+            found: rest.into(),
+        }
+        .at(span_of(text, scan, rest))),
+    }
+}
+
+/// Parses the name and parameters that follow `macro`.
+fn definition<'t>(text: &'t str, scan: &str, rest: &str) -> Result<(Span<'t>, Vec<String>)> {
+    let (name, params) = first_word(rest);
+    if !is_name(name) {
+        let found = rest.trim();
+        return Err(Error::Expected {
+            expected: "a macro name".into(),
+            found: found.into(),
+        }
+        .at(span_of(text, scan, found)));
+    }
+    if params.trim().is_empty() {
+        return Ok((span_of(text, scan, name), Vec::new()));
+    }
+    let params = params
+        .split(',')
+        .map(|param| match param.trim() {
+            param if is_name(param) => Ok(param.to_string()),
+            param => Err(Error::Expected {
+                expected: "a parameter name".into(),
+                found: param.into(),
+            }
+            .at(span_of(text, scan, param))),
+        })
+        .collect::<Result<_>>()?;
+    Ok((span_of(text, scan, name), params))
+}
+
+/// Replaces each word in `text` that has a replacement.
+fn substitute(text: &str, replacements: &HashMap<&str, String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(is_word_char) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_word_char(c)).unwrap_or(rest.len());
+        let word = &rest[..end];
+        out.push_str(replacements.get(word).map_or(word, String::as_str));
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Parses a line holding a single statement, returning the pair of the
+/// statement itself.
+fn statement(line: &str) -> Option<Pair<'_, Rule>> {
+    let elem = <Parser as ParserTrait<Rule>>::parse(Rule::elem, line)
+        .ok()?
+        .next()?;
+    if elem.as_span().end() != line.len() {
+        return None;
+    }
+    let mut p = elem;
+    while matches!(p.as_rule(), Rule::elem | Rule::label | Rule::directive) {
+        p = p.into_inner().next()?;
+    }
+    Some(p)
+}
+
+fn is_condition(rule: Rule) -> bool {
+    matches!(
+        rule,
+        Rule::cond_if | Rule::cond_ifdef | Rule::cond_ifndef | Rule::cond_else | Rule::cond_endif
+    )
+}
+
+/// Names defined in uncertain blocks are not known to be defined or not, and
+/// labels are known to be defined but not where.
+impl Names for Expander {
+    fn constant(&self, name: &str) -> Option<&Expr> {
+        self.constants
+            .get(name)
+            .filter(|_| !self.uncertain.contains(name))
+    }
+
+    fn constant_count(&self) -> usize {
+        self.constants.len()
+    }
+
+    fn label(&self, name: &str) -> Result<i64> {
+        Err(Error::UnresolvedLabel(name.into()))
+    }
+
+    fn defined(&self, name: &str) -> Result<bool> {
+        if self.uncertain.contains(name) {
+            return Err(Error::UnresolvedLabel(name.into()));
+        }
+        Ok(self.constants.contains_key(name) || self.labels.contains(name))
+    }
+}
+
+/// Replaces each block comment in `text` with spaces, keeping its line
+/// breaks, so that every offset in the result is the same as in `text`.
+fn blank_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        // Comment markers in strings and line comments are not comments.
+        let end = if let Some(comment) = rest.strip_prefix("/*") {
+            let end = comment.find("*/").map_or(rest.len(), |end| end + 4);
+            for c in rest[..end].chars() {
+                match c {
+                    '\r' | '\n' => out.push(c),
+                    _ => out.extend(std::iter::repeat_n(' ', c.len_utf8())),
+                }
+            }
+            rest = &rest[end..];
+            continue;
+        } else if c == '"' {
+            rest[1..]
+                .find(['"', '\n'])
+                .map_or(rest.len(), |end| end + 2)
+        } else if c == ';' || c == '#' || rest.starts_with("//") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else {
+            c.len_utf8()
+        };
+        out.push_str(&rest[..end]);
+        rest = &rest[end..];
+    }
+    out
+}
+
+/// The part of a line before any comment, skipping over strings.
+fn code(line: &str) -> &str {
+    let mut quoted = false;
+    let end = line
+        .char_indices()
+        .find(|&(i, c)| {
+            if c == '"' {
+                quoted = !quoted;
+            }
+            !quoted && (c == ';' || c == '#' || line[i..].starts_with("//"))
+        })
+        .map_or(line.len(), |(i, _)| i);
+    line[..end].trim_end()
+}
+
+/// Splits the arguments of a macro call at commas that are not in a string
+/// or in parentheses.
+fn split_args(args: &str) -> Vec<&str> {
+    let args = args.trim();
+    if args.is_empty() {
+        return Vec::new();
+    }
+    let mut split = Vec::new();
+    let (mut start, mut depth, mut quoted) = (0, 0usize, false);
+    for (i, c) in args.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth = depth.saturating_sub(1),
+            ',' if !quoted && depth == 0 => {
+                split.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(args[start..].trim());
+    split
+}
+
+/// Splits off the first word of a line, skipping leading whitespace.
+fn first_word(line: &str) -> (&str, &str) {
+    let line = line.trim_start();
+    line.split_at(line.find(|c| !is_word_char(c)).unwrap_or(line.len()))
+}
+
+/// Blanks out a line, keeping its line break and any block comment in it, so
+/// that comments that start or end in the line still do.
+fn blank(line: &str, scanned: &str) -> String {
+    let bytes = line
+        .bytes()
+        .zip(scanned.bytes())
+        .map(|(byte, scanned)| match byte {
+            b'\r' | b'\n' => byte,
+            _ if byte != scanned => byte,
+            _ => b' ',
+        });
+    // Only comments differ from their scan, and they are kept whole.
+    String::from_utf8(bytes.collect()).unwrap()
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn is_name(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && word.chars().all(is_word_char)
+}
+
+/// The span in `text` of `part`, which must be a slice of `scan`, the text
+/// with its block comments blanked out.
+fn span_of<'t>(text: &'t str, scan: &str, part: &str) -> Span<'t> {
+    let start = part.as_ptr() as usize - scan.as_ptr() as usize;
+    Span::new(text, start, start + part.len()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::{Assembler, Options},
+        assert_ok,
+    };
+
+    fn assemble(text: &str) -> Result<Vec<u8>> {
+        let source = Source::expand(text, &[])?;
+        let asm = Assembler::build_source(&source, &Options::default())?;
+        let mut dest = Vec::new();
+        asm.write_bin(&mut dest)?;
+        Ok(dest)
+    }
+
+    #[test]
+    fn test_expansion() {
+        let dest = assert_ok!(assemble(include_str!("../test_files/macros.asm")));
+        assert_eq!(
+            dest,
+            [
+                0x71, 0x02, 0x62, 0x10, 0xF2, 0x15, 0xF2, 0x07, 0x32, 0x00, 0x12, 0x06, 0x60, 0x1E,
+                0xF0, 0x15, 0xF0, 0x07, 0x30, 0x00, 0x12, 0x10, 0x12, 0x00
+            ]
+        );
+        // Labels in expansions do not take local labels after them away from
+        // the caller's scope.
+        let text =
+            "macro spin\nloop:\n    JP loop\nendm\nmain:\n    spin\n.end:\n    JP main.end\n";
+        assert_eq!(assert_ok!(assemble(text)), [0x12, 0x00, 0x12, 0x02]);
+
+        // Renamed labels are not exported.
+        let text = format!("{text}    spin\n");
+        let source = assert_ok!(Source::expand(&text, &[]));
+        let asm = assert_ok!(Assembler::build_source(&source, &Options::default()));
+        assert_eq!(asm.symbols(), [(0x200, "main"), (0x202, "main.end")]);
+
+        // Commas in strings and parentheses do not separate arguments.
+        assert_eq!(
+            split_args(r#" "a,b", (1, 2) , c "#),
+            [r#""a,b""#, "(1, 2)", "c"]
+        );
+        let msg = Source::expand(
+            "macro load name\ninclude name\nendm\nload \"a,b.asm\"\n",
+            &[],
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(msg.contains("Cannot read 'a,b.asm'"), "{msg}");
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let text = "macro twice reg\n    ADD reg, 1\n    ADD reg, 1\nendm\nmain:\n    twice V0\n\
+                    .loop:\n    JP .loop\nsprite:\n    db 0xF0, 0x90\n";
+        let source = assert_ok!(Source::expand(text, &[]));
+        let asm = assert_ok!(Assembler::build_source(&source, &Options::default()));
+        let mut dest = Vec::new();
+        assert_ok!(asm.write_debug_info("prog.asm", &mut dest));
+        let call = r#"[{"macro": "twice", "file": "prog.asm", "line": 6, "column": 5}]"#;
+        assert_eq!(
+            String::from_utf8(dest).unwrap(),
+            format!(
+                r#"{{
+  "lines": [
+    {{"address": 512, "size": 2, "file": "prog.asm", "line": 2, "column": 5, "expanded_from": {call}}},
+    {{"address": 514, "size": 2, "file": "prog.asm", "line": 3, "column": 5, "expanded_from": {call}}},
+    {{"address": 516, "size": 2, "file": "prog.asm", "line": 8, "column": 5, "expanded_from": []}},
+    {{"address": 518, "size": 2, "file": "prog.asm", "line": 10, "column": 5, "expanded_from": []}}
+  ],
+  "scopes": [
+    {{"name": "main", "start": 512, "end": 518, "labels": [{{"name": "main.loop", "address": 516}}]}},
+    {{"name": "sprite", "start": 518, "end": 520, "labels": []}}
+  ],
+  "data": [
+    {{"start": 518, "end": 520}}
+  ]
+}}
+"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_includes() {
+        let path = Path::new("test_files/include.asm");
+        let search = [PathBuf::from("test_files/include/lib")];
+        let source = assert_ok!(Source::load(path, &search, &[]));
+        let asm = assert_ok!(Assembler::build_source(&source, &Options::default()));
+        let mut dest = Vec::new();
+        assert_ok!(asm.write_bin(&mut dest));
+        assert_eq!(
+            dest,
+            [0x60, 0x00, 0x22, 0x06, 0x12, 0x00, 0xA2, 0x0C, 0xD0, 0x02, 0x00, 0xEE, 0xF0, 0x90]
+        );
+
+        let msg = Source::load(path, &[], &[]).unwrap_err().to_string();
+        assert!(msg.contains("test_files/include.asm:9:9"), "{msg}");
+        assert!(msg.contains("Cannot read 'test_files/font.asm'"), "{msg}");
+
+        let msg = Source::load(Path::new("test_files/include/cycle.asm"), &[], &[])
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("'cycle.asm' includes itself"), "{msg}");
+
+        let msg = Source::expand("include \"test_files/include/lib/font.asm\"\nsprite:", &[])
+            .and_then(|source| Assembler::build_source(&source, &Options::default()).map(|_| ()))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            msg.contains("test_files/include/lib/font.asm:3:17"),
+            "{msg}"
+        );
+
+        // Comment markers in a path are part of it.
+        let msg = Source::expand("include \"q/a;b#c//d.asm\" ; comment\n", &[])
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("Cannot read 'q/a;b#c//d.asm'"), "{msg}");
+    }
+
+    #[test]
+    fn test_conditional_expansion() {
+        // Excluded blocks include no files and define no macros.
+        let text = ".ifdef DEBUG\ninclude \"missing.asm\"\nmacro trace\n    CLS\nendm\n.else\n\
+                    macro trace\n    RET\nendm\n.endif\nstart:\n    trace\n\
+                    .if start - 0x200\n    CLS\n.endif\n";
+        assert_eq!(assert_ok!(assemble(text)), [0x00, 0xEE]);
+
+        let defines = [("DEBUG".to_string(), 1)];
+        let msg = Source::expand(text, &defines).unwrap_err().to_string();
+        assert!(msg.contains("Cannot read 'missing.asm'"), "{msg}");
+
+        // Names defined in a block the expander cannot evaluate are left to
+        // the assembler.
+        let text = ".if start\nMODE equ 1\n.endif\n.ifdef MODE\ninclude \"missing.asm\"\n.endif\n";
+        assert!(Source::expand(text, &[]).is_err());
+        let text = "MODE equ 2 * 3\n.if MODE - 6\nmacro a\nendm\n.endif\nmacro a\nendm\n";
+        assert_ok!(Source::expand(text, &[]));
+
+        // The expander and the assembler agree on which file is included,
+        // through constants defined after their use and in terms of defines.
+        let text = "SIZE equ DEPTH * 2\nDEPTH equ BASE + 1\n.if SIZE - 6\n\
+                    include \"missing.asm\"\n.else\ninclude \"test_files/include/quirk.asm\"\n\
+                    .endif\n.ifndef DEPTH\ninclude \"missing.asm\"\n.endif\n";
+        let defines = [("BASE".to_string(), 2)];
+        let source = assert_ok!(Source::expand(text, &defines));
+        let options = Options {
+            defines: defines.to_vec(),
+            ..Options::default()
+        };
+        let asm = assert_ok!(Assembler::build_source(&source, &options));
+        let mut dest = Vec::new();
+        assert_ok!(asm.write_bin(&mut dest));
+        assert_eq!(dest, [0x80, 0x16]);
+    }
+
+    #[test]
+    fn test_block_comments() {
+        // Includes and macros in block comments are ignored, as are block
+        // comments around the ones that are not.
+        let text = "/*\ninclude \"missing.asm\"\nmacro clear\n    CLS\nendm\n*/ macro clear\n\
+                    /* LD V0, 1 */ RET\nendm\nclear /* ; */\n    /* clear */ LD V1, \"/*\"\n";
+        let msg = assemble(text).unwrap_err().to_string();
+        assert!(msg.contains("10:24"), "{msg}");
+        let text = text.replace(", \"/*\"", ", 2");
+        assert_eq!(assert_ok!(assemble(&text)), [0x00, 0xEE, 0x61, 0x02]);
+    }
+
+    #[test]
+    fn test_warning_locations() {
+        let text = "include \"test_files/include/quirk.asm\"\n\
+                    macro twice\n    SHR V0, V1\n    SHR V0, V1\nendm\n    twice\n";
+        let source = assert_ok!(Source::expand(text, &[]));
+        let asm = assert_ok!(Assembler::build_source(&source, &Options::default()));
+        let warnings: Vec<_> = asm.warnings().iter().map(Warning::to_string).collect();
+        assert_eq!(warnings.len(), 3, "{warnings:?}");
+        assert!(
+            warnings[0].contains("test_files/include/quirk.asm:3:13"),
+            "{}",
+            warnings[0]
+        );
+        assert!(warnings[2].contains("4:13"), "{}", warnings[2]);
+        assert!(
+            warnings[2].contains("in expansion of macro 'twice'"),
+            "{}",
+            warnings[2]
+        );
+    }
+
+    #[test]
+    fn test_expansion_errors() {
+        let text = "macro set reg, value\n    LD reg, value\nendm\nset V0, 0x100\n";
+        let msg = assemble(text).unwrap_err().to_string();
+        assert!(msg.contains("2:12"), "{msg}");
+        assert!(msg.contains("LD V0, 0x100"), "{msg}");
+        assert!(msg.contains("4:1"), "{msg}");
+        assert!(msg.contains("in expansion of macro 'set'"), "{msg}");
+
+        let msg = assemble("macro set reg, value\nendm\nset V0\n")
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("takes 2 arguments, but 1 were given"), "{msg}");
+        assert!(msg.contains("1:7"), "{msg}");
+
+        let cases = [
+            "macro a\n    b\nendm\nmacro b\n    a\nendm\na\n",
+            "macro a\n    CLS\n",
+            "CLS\nendm\n",
+            "macro a\nendm\nmacro a\nendm\n",
+        ];
+        for text in cases {
+            let kind = assemble(text).map_err(Error::into_kind);
+            assert!(
+                matches!(
+                    kind,
+                    Err(Error::RecursiveMacro(_)
+                        | Error::Unclosed(_)
+                        | Error::Unmatched(..)
+                        | Error::DuplicateMacro(_))
+                ),
+                "{text}: {kind:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::{fmt, io::prelude::*, str::FromStr};
+
+/// The formats a program's labels can be exported in for emulators and
+/// debuggers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolFormat {
+    /// One `ADDR name` line per label, with the address in hex.
+    Plain,
+    /// A JSON array of `{"name": ..., "address": ...}` objects.
+    Json,
+    /// Octo `:const name 0xADDR` lines.
+    Octo,
+}
+
+impl SymbolFormat {
+    /// The extension that replaces the binary's to name a symbol file.
+    pub fn extension(self) -> &'static str {
+        match self {
+            SymbolFormat::Plain => "map",
+            SymbolFormat::Json => "symbols.json",
+            SymbolFormat::Octo => "sym",
+        }
+    }
+
+    /// Writes `symbols`, which are sorted by address.
+    pub fn write(self, symbols: &[(u16, &str)], mut dest: impl Write) -> std::io::Result<()> {
+        match self {
+            SymbolFormat::Plain => {
+                for (addr, name) in symbols {
+                    writeln!(dest, "{addr:04X} {name}")?;
+                }
+            }
+            SymbolFormat::Json => {
+                writeln!(dest, "[")?;
+                for (i, (addr, name)) in symbols.iter().enumerate() {
+                    let comma = if i + 1 < symbols.len() { "," } else { "" };
+                    let name = json_string(name);
+                    writeln!(dest, "  {{\"name\": {name}, \"address\": {addr}}}{comma}")?;
+                }
+                writeln!(dest, "]")?;
+            }
+            SymbolFormat::Octo => {
+                for (addr, name) in symbols {
+                    writeln!(dest, ":const {name} {addr:#05X}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SymbolFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "plain" => Ok(SymbolFormat::Plain),
+            "json" => Ok(SymbolFormat::Json),
+            "octo" => Ok(SymbolFormat::Octo),
+            _ => Err(format!(
+                "'{s}' is not a symbol format: expected plain, json or octo"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SymbolFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SymbolFormat::Plain => "plain",
+            SymbolFormat::Json => "json",
+            SymbolFormat::Octo => "octo",
+        })
+    }
+}
+
+/// Quotes `s` as a JSON string.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats() {
+        let symbols = [(0x200, "main"), (0x20A, "main.loop")];
+        let write = |format: SymbolFormat| {
+            let mut dest = Vec::new();
+            format.write(&symbols, &mut dest).unwrap();
+            String::from_utf8(dest).unwrap()
+        };
+        assert_eq!(write(SymbolFormat::Plain), "0200 main\n020A main.loop\n");
+        assert_eq!(
+            write(SymbolFormat::Json),
+            "[\n  {\"name\": \"main\", \"address\": 512},\n  \
+             {\"name\": \"main.loop\", \"address\": 522}\n]\n"
+        );
+        assert_eq!(
+            write(SymbolFormat::Octo),
+            ":const main 0x200\n:const main.loop 0x20A\n"
+        );
+        assert_eq!(json_string("a \"b\"\\\t"), r#""a \"b\"\\\u0009""#);
+    }
+}
@@ -0,0 +1,7 @@
+; header comment
+
+/* block
+   comment */
+start:   # trailing
+    SE V0, /* inline */ V1 // trailing
+    JP start ; done
@@ -0,0 +1,26 @@
+; Debug and release variants of one program
+.ifndef LEVEL
+LEVEL equ 1
+.endif
+
+start:
+.ifdef DEBUG
+    CALL trace
+.endif
+.if LEVEL - 1
+    ADD V0, LEVEL
+.else
+    ADD V0, 1
+.endif
+    JP start
+
+.ifdef DEBUG
+trace:
+.if 0
+    CLS
+.else
+    LD V1, LEVEL
+.endif
+    RET
+.endif
+end:
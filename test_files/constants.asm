@@ -0,0 +1,10 @@
+SPEED equ 3
+.define HEIGHT 0x5
+.define SCREEN 0x300
+    LD V0, SPEED
+    ADD V1, WIDTH
+    LD I, SCREEN
+    DRW V0, V1, HEIGHT
+    JP SCREEN
+    db SPEED, WIDTH
+WIDTH equ 64
@@ -0,0 +1,8 @@
+    JP after
+table:
+    db 0xF0, 0x90, 15
+words:
+    dw 0x1234, 1
+    .word 0xBEEF
+after:
+    JP after
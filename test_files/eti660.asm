@@ -0,0 +1,5 @@
+; ETI-660 programs are loaded at 0x600
+.org 0x600
+loop:
+    ADD V0, 1
+    JP loop
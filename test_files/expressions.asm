@@ -0,0 +1,10 @@
+WIDTH equ 64
+LAST equ WIDTH - 1
+    LD I, table + 4
+    SE V0, (WIDTH - 1) & 0x3F
+    LD V1, <table
+    LD V2, >table
+    ADD V3, -1
+    DRW V0, V1, LAST >> 3
+table:
+    dw table + 2, LAST * 2
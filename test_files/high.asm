@@ -0,0 +1,3 @@
+; Programs can be placed past 4K when the target has the memory for them
+.org 0x1800
+    ADD V0, 1
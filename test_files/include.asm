@@ -0,0 +1,12 @@
+; Included files are found next to this file, then in each -I directory
+include "include/defs.asm"
+
+start:
+    clear V0
+    CALL draw
+    JP start
+
+include "font.asm"
+
+sprite:
+    incbin "include/sprite.bin"
@@ -0,0 +1,6 @@
+; Definitions shared between files
+HEIGHT equ 2
+
+macro clear reg
+    LD reg, 0
+endm
@@ -0,0 +1,4 @@
+draw:
+    LD I, sprite
+    DRW V0, V0, HEIGHT
+    RET
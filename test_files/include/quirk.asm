@@ -0,0 +1,3 @@
+; An instruction that behaves differently between platforms
+shift:
+    SHR V0, V1
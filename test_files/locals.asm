@@ -0,0 +1,20 @@
+; Local labels belong to the global label before them
+first:
+    LD V0, 0
+.loop:
+    ADD V0, 1
+    SE V0, 3
+    JP .loop
+second:
+.loop:
+    JP .loop
+    JP first.loop
+; Anonymous labels are counted forwards with + and backwards with -
+@@:
+    JP -
+    JP +
+    JP ++
+@@:
+    CLS
+@@:
+    JP --
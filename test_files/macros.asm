@@ -0,0 +1,23 @@
+; Macros with parameters, local labels and nested calls
+macro inc reg, amount
+    ADD reg, amount
+endm
+
+macro wait reg, ticks
+    LD reg, ticks
+    LD DT, reg
+loop:
+    LD reg, DT
+    SE reg, 0
+    JP loop
+endm
+
+macro pause
+    wait V0, 30
+endm
+
+start:
+    inc V1, 2
+    wait V2, 0x10
+    pause
+    JP start
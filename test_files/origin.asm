@@ -0,0 +1,10 @@
+.org 0x600
+start:
+    JP data
+    db 1
+.align 4
+aligned:
+    .fill 3, 0xAA
+.org 0x610
+data:
+    db 2
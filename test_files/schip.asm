@@ -0,0 +1,12 @@
+; SUPER-CHIP instructions
+    HIGH
+    LD V0, 3
+    LD HF, V0
+    DRW V1, V2, 0
+    SCD 4
+    SCR
+    SCL
+    LD R, V7
+    LD V7, R
+    LOW
+    EXIT
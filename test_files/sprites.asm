@@ -0,0 +1,11 @@
+    LD I, box
+    DRW V0, V1, 4
+    LD I, bar
+    DRW V0, V1, 2
+box:
+    sprite "########",
+           "#......#",
+           "10000001",
+           "11111111"
+bar:
+    sprite "####...."
@@ -0,0 +1,16 @@
+; XO-CHIP instructions
+    PLANE 3
+    LD I, LONG sprite
+    SE V0, 0
+    LD I, LONG sprite
+    SAVE V1 - V4
+    LOAD V4 - V1
+    AUDIO
+    LD PITCH, V2
+    SCU 2
+end:
+    JP end
+; Data past 4K, which only LD I, LONG can reach
+    .org 0x1200
+sprite:
+    db 0xFF, 0x81